@@ -0,0 +1,70 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use slde::crdt::{AWSet, Crdt};
+use slde::protocol::{self, Encoding, Request};
+
+// a list edited by a few replicas, like a family sharing it
+fn family_list(items: usize, replicas: usize) -> AWSet {
+    let mut list = AWSet::new();
+    list.set_id("bench".to_string());
+    for i in 0..items {
+        let replica = format!("replica-{}", i % replicas);
+        list.add(&format!("item {}", i), (i % 5) as u64 + 1, 0, &replica);
+    }
+    list
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    for items in [10, 100, 1000] {
+        let list = family_list(items, 4);
+        let request = Request::ReplicaWrite { list: list.clone() };
+        group.bench_with_input(BenchmarkId::new("json", items), &request, |b, request| {
+            b.iter(|| black_box(protocol::encode_request(request, Encoding::Json)))
+        });
+        group.bench_with_input(BenchmarkId::new("messagepack", items), &request, |b, request| {
+            b.iter(|| black_box(protocol::encode_request(request, Encoding::MessagePack)))
+        });
+    }
+    group.finish();
+}
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    for items in [10, 100, 1000] {
+        let request = Request::ReplicaWrite { list: family_list(items, 4) };
+        let json = protocol::encode_request(&request, Encoding::Json);
+        let messagepack = protocol::encode_request(&request, Encoding::MessagePack);
+        println!("{} items: json {} bytes, messagepack {} bytes", items, json.len(), messagepack.len());
+        group.bench_with_input(BenchmarkId::new("json", items), &json, |b, json| {
+            b.iter(|| black_box(protocol::decode_request(json).unwrap()))
+        });
+        group.bench_with_input(BenchmarkId::new("messagepack", items), &messagepack, |b, messagepack| {
+            b.iter(|| black_box(protocol::decode_request(messagepack).unwrap()))
+        });
+    }
+    group.finish();
+}
+
+fn merge(c: &mut Criterion) {
+    let mut group = c.benchmark_group("merge");
+    for items in [10, 100, 1000] {
+        let local = family_list(items, 4);
+        // the same list after another replica edited half of it
+        let mut remote = local.clone();
+        let ids: Vec<String> = local.elements().into_iter().map(|(item_id, _)| item_id.clone()).collect();
+        for item_id in ids.iter().step_by(2) {
+            remote.update_item_amounts(item_id, 3, 1, "replica-remote").unwrap();
+        }
+        group.bench_with_input(BenchmarkId::from_parameter(items), &(local, remote), |b, (local, remote)| {
+            b.iter(|| {
+                let mut merged = local.clone();
+                merged.merge(remote);
+                black_box(merged)
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encode, decode, merge);
+criterion_main!(benches);
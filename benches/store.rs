@@ -0,0 +1,64 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use slde::crdt::{AWSet, Quantity};
+use slde::storage::{StorageConfig, StorageEngine};
+use slde::store::ListStore;
+
+const ENGINES: [StorageEngine; 3] = [StorageEngine::Json, StorageEngine::Files, StorageEngine::Sled];
+const LISTS: usize = 1000;
+
+fn list(id: usize) -> AWSet {
+    let mut list = AWSet::new();
+    list.set_id(format!("list-{}", id));
+    for i in 0..10 {
+        list.add(&format!("item {}", i), 1, 0, "replica-0");
+    }
+    list
+}
+
+// a store holding `lists` lists on the engine behind the write-ahead log, as a server opens it
+fn open(engine: StorageEngine, lists: usize) -> (ListStore, std::path::PathBuf) {
+    let data_dir = std::env::temp_dir().join(format!("slde_bench_store_{}", uuid::Uuid::new_v4()));
+    let mut snapshot = engine.open(engine.default_path(&data_dir)).unwrap();
+    snapshot.put_all(&(0..lists).map(list).collect::<Vec<_>>()).unwrap();
+    drop(snapshot);
+    let config = StorageConfig { engine, ..StorageConfig::default() };
+    let store = ListStore::open(Box::new(config.open("0", &data_dir).unwrap())).unwrap();
+    (store, data_dir)
+}
+
+// a read is answered from memory, so its latency doesn't grow with the lists stored
+fn read(c: &mut Criterion) {
+    let mut group = c.benchmark_group("store_read");
+    for engine in ENGINES {
+        for lists in [10, 1000, 10000] {
+            let (store, data_dir) = open(engine, lists);
+            let key = format!("list-{}", lists / 2);
+            group.bench_with_input(BenchmarkId::new(format!("{:?}", engine), lists), &key, |b, key| {
+                b.iter(|| black_box(store.get(key).cloned()))
+            });
+            let _ = std::fs::remove_dir_all(&data_dir);
+        }
+    }
+    group.finish();
+}
+
+// a write goes through the log and, every checkpoint, to the engine
+fn write(c: &mut Criterion) {
+    let mut group = c.benchmark_group("store_write");
+    for engine in ENGINES {
+        let (mut store, data_dir) = open(engine, LISTS);
+        let mut changed = store.get_or_new(&format!("list-{}", LISTS / 2));
+        let (milk, _) = changed.add("milk", 1, 0, "replica-1");
+        group.bench_function(BenchmarkId::from_parameter(format!("{:?}", engine)), |b| {
+            b.iter(|| {
+                changed.increment(&milk, Quantity::Bought, 1, "replica-1").unwrap();
+                store.put(changed.clone()).unwrap();
+            })
+        });
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+    group.finish();
+}
+
+criterion_group!(benches, read, write);
+criterion_main!(benches);
@@ -13,20 +13,33 @@ const fetchGeneratedId = async () => {
 };
 
 // Define the type for list.json data
+type Sibling = {
+    replica: string;
+    bought: number;
+    target: number;
+};
+
+type Modified = {
+    // milliseconds since the epoch
+    at: number;
+    by: string;
+};
+
 type ListData = {
-    item_name: string;
+    id: string;
+    // more than one when the item was renamed concurrently
+    names: string[];
     bought: number;
     target: number;
-    replica: string;
-    timestamp: number;
-    deleted: boolean;
+    // the amounts set concurrently, when they conflict
+    siblings?: Sibling[];
+    // missing for items not changed since the list was migrated
+    modified?: Modified;
 };
 
 type ListJson = {
-    [key: string]: {
-        c: { replica: string; timestamp: number }[];
-        s: ListData[];
-    };
+    id: string;
+    items: ListData[];
 };
 
 
@@ -77,13 +90,11 @@ const ListView: React.FC = () => {
         }
     };
 
-    const handleRemove = async (itemName: string) => {
+    const handleRemove = async (itemId: string) => {
         const change = {
             type: "remove",
             list_id: id,
-            item_name: itemName,
-            target: 0,
-            bought: 0,
+            item_id: itemId,
             replica: userId
         };
         try {
@@ -97,29 +108,24 @@ const ListView: React.FC = () => {
     };
 
     // Handle input changes
-    const handleInputChange = (itemName: string, value: string) => {
+    const handleInputChange = (itemId: string, value: string) => {
         const numericValue = parseInt(value, 10) || 0;
         setInputValue((prevState) => ({
             ...prevState,
-            [itemName]: numericValue,
+            [itemId]: numericValue,
         }));
     };
 
-    const handleUpdate = async (itemName: string, amount:number, old_bought: number, old_target: number) => {
-        const value = inputValue[itemName] || 0;
-        let target = old_target
-        let bought = old_bought
-        if(value >=  0){
-            target += value
-        }else{
-            bought -= value
-        }
+    const handleUpdate = async (itemId: string, amount:number, old_bought: number, old_target: number) => {
+        const value = inputValue[itemId] || 0;
+        // a positive amount is more to buy, a negative one was bought; sent as increments
+        // so changes made at the same time by others add up
         const change = {
-            type: "update",
+            type: "increment",
             list_id: id,
-            item_name: itemName,
-            target: target,
-            bought: bought,
+            item_id: itemId,
+            quantity: value >= 0 ? "target" : "bought",
+            amount: Math.abs(value),
             replica: userId
         };
         try {
@@ -132,6 +138,40 @@ const ListView: React.FC = () => {
         }
     };
 
+    // picking one of the names or amounts written concurrently replaces them all
+    const handleRename = async (itemId: string, itemName: string) => {
+        const change = {
+            type: "rename",
+            list_id: id,
+            item_id: itemId,
+            item_name: itemName,
+            replica: userId
+        };
+        try {
+            await axios.post('http://localhost:5000/changes', change);
+        } catch (error) {
+            console.error('Error renaming item:', error);
+            alert('Failed to rename item');
+        }
+    };
+
+    const handleKeep = async (itemId: string, sibling: Sibling) => {
+        const change = {
+            type: "update",
+            list_id: id,
+            item_id: itemId,
+            target: sibling.target,
+            bought: sibling.bought,
+            replica: userId
+        };
+        try {
+            await axios.post('http://localhost:5000/changes', change);
+        } catch (error) {
+            console.error('Error updating item:', error);
+            alert('Failed to update item');
+        }
+    };
+
     useEffect(() => {
         const fetchList = async () => {
             try {
@@ -139,12 +179,10 @@ const ListView: React.FC = () => {
                 // Fetch the entire JSON
                 const response = await axios.get<ListJson>(request_url);
                 const data = response.data;
-                // Get the first key in the JSON (since it's dynamic)
-                const firstKey = Object.keys(data)[0];
 
-                // Check if the id from the URL matches the first key
-                if (firstKey === id) {
-                    setList(data[firstKey].s); // Set the shopping list from the "s" field
+                // Check if the id from the URL matches the list
+                if (data.id === id) {
+                    setList(data.items);
                     setError(null);
                 } else {
                     setList(null);
@@ -166,29 +204,41 @@ const ListView: React.FC = () => {
             {error && <h2>{error}</h2>}
             {list && list.length > 0 ? (
                 list.map((item, index) => (
-                    !item.deleted && (
-                        <li key={index}>
-                            <p><strong>{item.item_name}</strong></p>
-                            <button onClick={() => handleRemove(item.item_name)}>Remove</button>
+                    <li key={index}>
+                        <p><strong>{item.names.join(' / ')}</strong></p>
+                        {item.names.length > 1 && item.names.map((itemName) => (
+                            <button key={itemName} onClick={() => handleRename(item.id, itemName)}>Keep "{itemName}"</button>
+                        ))}
+                        <button onClick={() => handleRemove(item.id)}>Remove</button>
+                        <p>
+                            Need: {item.target - item.bought}
+                        </p>
+                        {item.modified && (
                             <p>
-                                Need: {item.target - item.bought}
+                                Last changed {new Date(item.modified.at).toLocaleString()} by {item.modified.by}
+                            </p>
+                        )}
+                        {item.siblings && item.siblings.map((sibling, i) => (
+                            <p key={i}>
+                                {sibling.replica} set {sibling.target - sibling.bought}
+                                <button onClick={() => handleKeep(item.id, sibling)}>Keep</button>
                             </p>
-                            <form onSubmit={(e) => handleUpdate(item.item_name, e, item.bought, item.target )}>
-                                <label>
-                                    Change amount needed:
-                                    <input
-                                        type="number"
-                                        value={inputValue[item.item_name] || ''}
-                                        onChange={(e) =>
-                                            handleInputChange(item.item_name, e.target.value)
-                                        }
-                                        required
-                                    />
-                                </label>
-                                <button type="submit">Update</button>
-                            </form>
-                        </li>
-                    )
+                        ))}
+                        <form onSubmit={(e) => handleUpdate(item.id, e, item.bought, item.target )}>
+                            <label>
+                                Change amount needed:
+                                <input
+                                    type="number"
+                                    value={inputValue[item.id] || ''}
+                                    onChange={(e) =>
+                                        handleInputChange(item.id, e.target.value)
+                                    }
+                                    required
+                                />
+                            </label>
+                            <button type="submit">Update</button>
+                        </form>
+                    </li>
                 ))
             ) : (
                 <p>No items found or loading...</p>
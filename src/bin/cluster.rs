@@ -0,0 +1,32 @@
+use std::env;
+use slde::protocol::{self, Encoding, Request, Response};
+
+// admin commands for a running cluster
+// cargo run --bin cluster leave <port>: the server on that port hands its lists off and exits
+// cargo run --bin cluster hints <port>: lists the hints the server still has to deliver
+// cargo run --bin cluster metrics <port>: the tombstones the server collected
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let message = match (args.len(), args.get(1).map(|x| x.as_str())) {
+        (3, Some("leave")) => Request::Leave,
+        (3, Some("hints")) => Request::Hints,
+        (3, Some("metrics")) => Request::Metrics,
+        _ => {
+            println!("Usage: cargo run --bin cluster <leave|hints|metrics> <port>");
+            std::process::exit(1);
+        }
+    };
+
+    let context = zmq::Context::new();
+    let requester = context.socket(zmq::REQ).unwrap();
+    let address = format!("tcp://localhost:{}", args[2]);
+    assert!(requester.connect(&address).is_ok());
+
+    // admin messages are rare, json keeps them readable when sniffing the wire
+    requester.send(protocol::encode_request(&message, Encoding::Json), 0).unwrap();
+    let response = requester.recv_bytes(0).unwrap();
+    match protocol::decode_response(&response) {
+        Response::Hints { summary } => println!("Response: {}", summary),
+        response => println!("Response: {:?}", response),
+    }
+}
@@ -0,0 +1,56 @@
+use std::env;
+use slde::storage::{self, StorageEngine};
+use slde::wal::LoggedStorage;
+
+// copies the lists of a stopped server from one storage engine to another
+// cargo run --bin migrate_storage <json|files|sled> <from path> <json|files|sled> <to path> [<log>]
+// with the server's write-ahead log, the writes still in it are checkpointed into the source first
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 5 && args.len() != 6 {
+        println!("Usage: cargo run --bin migrate_storage <json|files|sled> <from path> <json|files|sled> <to path> [<log>]");
+        std::process::exit(1);
+    }
+    let (from_engine, to_engine) = match (StorageEngine::parse(&args[1]), StorageEngine::parse(&args[3])) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut from = match from_engine.open(&args[2]) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Failed to open {}: {}", args[2], e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(log) = args.get(5) {
+        let checkpointed = LoggedStorage::open(from, log, 1).and_then(|mut logged| {
+            logged.checkpoint()?;
+            Ok(logged)
+        });
+        from = match checkpointed {
+            Ok(x) => Box::new(x),
+            Err(e) => {
+                eprintln!("Failed to replay {}: {}", log, e);
+                std::process::exit(1);
+            }
+        };
+    }
+    let mut to = match to_engine.open(&args[4]) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Failed to open {}: {}", args[4], e);
+            std::process::exit(1);
+        }
+    };
+    match storage::copy(from.as_ref(), to.as_mut()) {
+        Ok(copied) => println!("Copied {} lists from {} to {}", copied, args[2], args[4]),
+        Err(e) => {
+            eprintln!("Failed to copy the lists: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -1,9 +1,29 @@
+use std::env;
+use slde::config::{self, ProxyConfig};
+use slde::{info, log};
+
+// proxy [--config <path>] [--<key> <value> ...]
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let config = match config::load::<ProxyConfig>("proxy", &args) {
+        Ok((config, positional)) if positional.is_empty() => config,
+        Ok(_) => {
+            println!("Usage: cargo run --bin proxy [--config <path>] [--<key> <value> ...]");
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    log::set_level(config.log_level);
+
     let context = zmq::Context::new();
     let frontend = context.socket(zmq::ROUTER).unwrap();
     let backend = context.socket(zmq::DEALER).unwrap();
-    assert!(frontend.bind("tcp://*:5559").is_ok());
-    assert!(backend.bind("tcp://*:5560").is_ok());
+    assert!(frontend.bind(&config.frontend).is_ok());
+    assert!(backend.bind(&config.backend).is_ok());
+    info!("proxying {} to {}", config.frontend, config.backend);
 
     zmq::proxy(&frontend, &backend).unwrap();
 }
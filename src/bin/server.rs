@@ -1,119 +1,259 @@
-use std::{collections::HashMap, env, fs};
-use serde_json::{Value, json};
-use std::fs::OpenOptions;
-use std::io::Write;
-use slde::crdt::AWSet;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-
-struct TimeoutTable {
-    server_timestamp: HashMap<String, SystemTime>,
-}
+use std::{collections::{HashMap, VecDeque}, env, fs};
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+use slde::config::{self, ServerConfig};
+use slde::crdt::{AWSet, Causal, CausalOrder, Crdt, SCHEMA_VERSION};
+use slde::failure_detector::{self, FailureDetector};
+use slde::hints::{HintQueue, HintQueueConfig};
+use slde::membership::{handoff_targets, Membership};
+use slde::merkle::{self, AntiEntropyConfig, MerkleTree};
+use slde::oplog::{Op, OpLog, OpLogStore};
+use slde::protocol::{self, Encoding, MerkleQuery, ProtocolError, Request, Response};
+use slde::quorum::QuorumConfig;
+use slde::ring::HashRing;
+use slde::log;
+use slde::storage::{StorageConfig, StorageEngine};
+use slde::store::ListStore;
+use slde::transport;
+use slde::{debug, error, info, warn};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// number of positions each server takes on the consistent-hashing ring
+const VIRTUAL_NODES: usize = 64;
+
+// how long a request to another server may take before we give up on it
+const REQUEST_TIMEOUT_MS: u64 = 5000;
+
+// how often the pending hints are checked against the failure detector
+const HINT_DELIVERY_INTERVAL: Duration = Duration::from_secs(2);
 
 struct Servers {
-    ports: HashMap<String, String>,
+    membership: Membership,
+    // where the membership view is kept between restarts
+    members_file: PathBuf,
+    quorum: QuorumConfig,
+    detector: Arc<Mutex<FailureDetector>>,
+    hints: Arc<Mutex<HintQueue>>,
+    // encoding of the requests this server sends
+    encoding: Encoding,
     context: zmq::Context,
 }
 
-impl TimeoutTable {
-    // this function updates the time saved in the table to the now time
-    fn update_timestamp(&mut self, server_id: &str) {
-        self.server_timestamp
-            .insert(server_id.to_string(), SystemTime::now());
+impl Servers {
+    // abstraction to send messages to other workers
+    fn send_to_worker(&self, server_id: &str, request: &Request) -> Response {
+        let port = match self.membership.port(server_id) {
+            Some(x) => x,
+            None => {
+                warn!("Server {} is not a member of the cluster", server_id);
+                return ProtocolError::Unavailable { server: server_id.to_string() }.into();
+            }
+        };
+        match send_to_port(&self.context, port, request, self.encoding) {
+            Response::Error { error: ProtocolError::Unavailable { .. } } => {
+                self.detector.lock().unwrap().report_failure(server_id);
+                ProtocolError::Unavailable { server: server_id.to_string() }.into()
+            }
+            response => response,
+        }
+    }
+
+    // whether the failure detector currently sees the server as alive
+    fn is_alive(&self, server_id: &str) -> bool {
+        self.detector.lock().unwrap().is_alive(server_id)
     }
 
-    // this function verifies if the server is timed out
-    fn is_timed_out(&self, server_id: &str, timeout_duration: Duration) -> bool {
-        let timestamp = self.server_timestamp.get(server_id).unwrap_or(&UNIX_EPOCH);
-        let elapsed = SystemTime::now()
-            .duration_since(*timestamp)
-            .unwrap_or_default();
-        elapsed <= timeout_duration
+    // called after every membership change so the detector watches the right peers
+    fn sync_detector(&self) {
+        self.detector.lock().unwrap().sync_peers(&self.membership.members());
     }
-}
 
-impl Servers {
-    // abstraction to send messages to other workers
-    fn send_to_worker(&self, server_id: String, message: String) -> String {
-        let requester = self.context.socket(zmq::REQ).unwrap();
-        let address = format!("tcp://localhost:{}", self.ports[&server_id]);
-        assert!(requester.connect(&address).is_ok());
-        requester.send(&message, 0).unwrap();
-
-        let response = match requester.recv_msg(0) {
-            Ok(x) => x,
-            Err(e) => {
-                println!("Failed to send message: {}", e);
-                return "".to_string();
+    fn save_membership(&self) {
+        if let Err(e) = self.membership.save(&self.members_file) {
+            warn!("Failed to store the membership view: {}", e);
+        }
+    }
+
+    // sends the current membership view to every other member
+    fn broadcast_members(&self, server_id: &str) {
+        let request = Request::Members { members: self.membership.view() };
+        for member in self.membership.ids() {
+            if member == server_id {
+                continue;
             }
-        };
-        assert!(requester.disconnect(&address).is_ok());
-        println!("Got response:{}", response.as_str().unwrap());
-        let response_string = match response.as_str() {
-            Some(x) => x,
-            None => "",
-        };
-        return response_string.to_string();
+            let response = self.send_to_worker(&member, &request);
+            if response != Response::Ack {
+                warn!("Failed to update membership on server {}: {:?}", member, response);
+            }
+        }
     }
 }
 
+fn send_to_port(context: &zmq::Context, port: &str, request: &Request, encoding: Encoding) -> Response {
+    let timeout = Duration::from_millis(REQUEST_TIMEOUT_MS);
+    let response = transport::call(context, port, request, encoding, timeout);
+    debug!("Got response:{:?}", response);
+    response
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: cargo run --bin server <id>")
-    }
-    let id = &args[1];
-    let ports_contents = fs::read_to_string("data/ports.json")?;
-    let json: Value = match serde_json::from_str(&ports_contents) {
-        Ok(value) => value,
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    // server <id> [--join <my port> <seed port>] [--config <path>] [--<key> <value> ...]
+    let join_args = match args.iter().position(|arg| arg == "--join") {
+        Some(i) if i + 2 < args.len() => {
+            let join: Vec<String> = args.drain(i..i + 3).collect();
+            Some((join[1].clone(), join[2].clone()))
+        }
+        Some(_) => {
+            eprintln!("--join takes this server's port and the seed's port");
+            std::process::exit(1);
+        }
+        None => None,
+    };
+    let (config, positional) = match config::load::<ServerConfig>("server", &args) {
+        Ok(x) => x,
         Err(e) => {
-            eprintln!("Error parsing ports JSON: {:?}", e);
-            panic!();
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
     };
+    if positional.len() != 1 {
+        println!("Usage: cargo run --bin server <id> [--join <port> <seed port>] [--config <path>] [--<key> <value> ...]");
+        std::process::exit(1);
+    }
+    let id = &positional[0];
+    if let Err(e) = validate_args(id, join_args.as_ref()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    log::set_level(config.log_level);
 
-    let json_clone = json.clone();
-
-    // create the port HashMap
-    let ports_hashmap: HashMap<String, String> = match json_clone {
-        Value::Object(obj) => obj
-            .into_iter()
-            .filter_map(|(k, v)| match v {
-                Value::String(s) => Some((k, s)),
-                _ => None, // Ignore non-string values
-            })
-            .collect(),
-        _ => panic!("Expected a JSON object"),
+    // every setting is checked before the server touches its data. A joining server only
+    // knows itself until the seed answers with the full view, so it doesn't read the peers
+    let settings = match &join_args {
+        Some((port, _)) => Ok(HashMap::from([(id.clone(), port.clone())])),
+        None => config.peers(),
+    }
+    .and_then(|peers| Ok((peers, config.encoding()?, config.quorum()?, config.storage()?)))
+    .and_then(|(peers, encoding, quorum, storage)| {
+        Ok((peers, encoding, quorum, storage, config.failure_detector(encoding)?))
+    });
+    let (ports_hashmap, encoding, quorum, storage_config, detector_config) = match settings {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Invalid server configuration: {}", e);
+            std::process::exit(1);
+        }
     };
+    if !ports_hashmap.contains_key(id) {
+        eprintln!("Server {} is not in the peer list, start it with --join <port> <seed port>", id);
+        std::process::exit(1);
+    }
 
-    let mut shopping_list: HashMap<String, AWSet> = load_shopping_list(id.to_owned());
-
-    // create the Timestamps HashMap
-    let mut timestamps: HashMap<String, SystemTime> = HashMap::new();
+    let data_dir = config.data_dir_for(id);
+    fs::create_dir_all(&data_dir)?;
+    adopt_legacy_files(id, &data_dir, &storage_config);
 
-    let servers = Servers {
-        ports: ports_hashmap,
-        context: zmq::Context::new(),
+    let data_path = storage_config.path_for(id, &data_dir);
+    let storage = match storage_config.open(id, &data_dir) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Failed to open the {:?} storage at {}: {}", storage_config.engine, data_path.display(), e);
+            std::process::exit(1);
+        }
     };
+    let mut store = match ListStore::open(Box::new(storage)) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Failed to load the lists: {}", e);
+            std::process::exit(1);
+        }
+    };
+    // only the single json file can hold lists from before the schema version
+    let collected = match storage_config.engine {
+        StorageEngine::Json => migrate_data_file(&data_path, &mut store),
+        _ => 0,
+    };
+
+    let detector = Arc::new(Mutex::new(FailureDetector::new(id, detector_config)));
+
+    let hints = Arc::new(Mutex::new(HintQueue::open(data_dir.join("hints"), HintQueueConfig::default())?));
 
-    for i in 0..7 {
-        let server_id = i.to_string();
-        timestamps.insert(server_id, UNIX_EPOCH);
+    let mut oplogs = OpLogStore::open(data_dir.join("oplog"))?;
+
+    // the servers that joined or left since the peer list was written are in the saved view
+    let members_file = data_dir.join("members.json");
+    let mut membership = Membership::new(VIRTUAL_NODES, ports_hashmap);
+    match Membership::load(VIRTUAL_NODES, &members_file) {
+        Ok(Some(saved)) => {
+            membership.apply(saved.view());
+        }
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("Failed to read the membership view: {}", e);
+            std::process::exit(1);
+        }
     }
 
-    let mut timeout_table = TimeoutTable {
-        server_timestamp: timestamps,
+    let mut servers = Servers {
+        membership,
+        members_file,
+        quorum,
+        detector: detector.clone(),
+        hints: hints.clone(),
+        encoding,
+        context: zmq::Context::new(),
     };
 
     // connect to proxy
     let context: zmq::Context = zmq::Context::new();
     let proxy_responder: zmq::Socket = context.socket(zmq::REP).unwrap();
-    assert!(proxy_responder.connect("tcp://localhost:5560").is_ok());
+    if let Err(e) = proxy_responder.connect(&config.proxy) {
+        eprintln!("Failed to connect to the proxy at {}: {}", config.proxy, e);
+        std::process::exit(1);
+    }
 
     // socket to recieve messages from other servers
     let server_responder = context.socket(zmq::REP).unwrap();
-    let my_ip = format!("tcp://*:{}", servers.ports[id]);
-    println!("my address is: {}", my_ip);
-    assert!(server_responder.bind(&my_ip).is_ok());
+    let my_ip = config.bind_for(servers.membership.port(id).unwrap());
+    info!("my address is: {}", my_ip);
+    if let Err(e) = server_responder.bind(&my_ip) {
+        eprintln!("Failed to listen on {}: {}", my_ip, e);
+        std::process::exit(1);
+    }
+
+    // announce ourselves to the seed, the lists we now own are handed off by the
+    // previous owners once they learn the new view
+    if let Some((port, seed_port)) = &join_args {
+        let request = Request::Join { id: id.clone(), port: port.clone() };
+        match send_to_port(&servers.context, seed_port, &request, encoding) {
+            Response::Members { members } => {
+                servers.membership.apply(members);
+                servers.save_membership();
+                info!("joined the cluster, members: {:?}", servers.membership.ids());
+            }
+            response => {
+                eprintln!("Seed on port {} refused the join: {:?}", seed_port, response);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    servers.sync_detector();
+    spawn_hint_delivery(hints, detector.clone(), encoding);
+    let anti_entropy = AntiEntropyConfig { encoding, ..AntiEntropyConfig::default() };
+    let merkle_depth = anti_entropy.depth;
+    // the tree shared with each peer, built when its anti-entropy round asks for the root
+    let mut merkle_trees: HashMap<String, MerkleTree> = HashMap::new();
+    merkle::spawn_anti_entropy(
+        id.clone(),
+        servers.membership.port(id).unwrap().clone(),
+        detector.clone(),
+        anti_entropy,
+    );
+    failure_detector::spawn_gossip(detector);
 
     let items = &mut [
         proxy_responder.as_poll_item(zmq::POLLIN),
@@ -129,7 +269,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 let string = match proxy_responder.recv_msg(0) {
                     Ok(x) => x,
                     Err(_e) => {
-                        println!("Failed to extract message!");
+                        debug!("Failed to extract message!");
                         break;
                     }
                 };
@@ -139,70 +279,36 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     0
                 };
 
-                let message = string.as_str().unwrap();
-                
-                //read message
-                let message_parsed = if message.starts_with("READ") {
-                    &message[4..]
-                } else {
-                    // assume its write
-                    message
+                let (request, encoding) = match protocol::decode_request(&string) {
+                    Ok((request, encoding)) => (Ok(request), encoding),
+                    Err(error) => (Err(error), Encoding::detect(&string)),
                 };
-
-                //case of read message
-                if message.starts_with("READ") {
-                    let list_owner_id = match get_owner_id(message_parsed.to_string()) {
-                        Some(x) => x,
-                        None => {
-                            println!("Failed to retrieve owner");
-                            break;
+                let response = match request {
+                    Ok(
+                        request @ (Request::Read { .. }
+                        | Request::Write { .. }
+                        | Request::WriteDelta { .. }
+                        | Request::Apply { .. }),
+                    ) => {
+                        let list_id = request.list_id().unwrap_or_default();
+                        let replicas = servers.membership.ring().preference_list(&list_id, servers.quorum.n);
+                        if replicas.is_empty() {
+                            ProtocolError::BadRequest { reason: "no owner for the list".to_string() }.into()
+                        } else if replicas.contains(id) {
+                            // any home replica coordinates; forwarding from one replica to another
+                            // could block both, each waiting on the other's single socket
+                            handle_client_request(&servers, id, &mut store, &mut oplogs, request)
+                        } else {
+                            forward_to_replica(&servers, &replicas, &request)
                         }
-                    };
-                    if &list_owner_id == id {
-                        let _ = dynamo_style_read(&servers, message_parsed, id);
-                    } else {
-                        println!(
-                            "I am not the owner of the list, sending to server reader {}",
-                            list_owner_id
-                        );
-                        let response = servers.send_to_worker(list_owner_id, string.as_str().unwrap().to_string());
-                        proxy_responder.send(&response, 0).unwrap();
                     }
-                    break;
-                }
-
-                // case of write message
-
-                // get the json file
-                let json: Value = match serde_json::from_str(string.as_str().unwrap()) {
-                    Ok(value) => value,
-                    Err(e) => {
-                        eprintln!("Error parsing JSON: {:?}", e);
-                        panic!();
+                    Ok(request) => ProtocolError::BadRequest {
+                        reason: format!("{:?} is not a client request", request),
                     }
+                    .into(),
+                    Err(error) => error.into(),
                 };
-                // get the list id
-                let mut list_id = String::new();
-                if let Some(first_key) = get_first_key(&json) {
-                    list_id = first_key;
-                } else {
-                    println!("Not a Json file");
-                }
-                // get owner id
-                let list_owner_id = match get_owner_id(list_id) {
-                    Some(x) => x,
-                    None => {
-                        println!("Failed to retrieve owner");
-                        break;
-                    }
-                };
-                //compare with self
-                if &list_owner_id != id {
-                    // send message to owner
-                    let response =
-                        servers.send_to_worker(list_owner_id, string.as_str().unwrap().to_string());
-                    proxy_responder.send(&response, 0).unwrap();
-                }
+                proxy_responder.send(protocol::encode_response(&response, encoding), 0).unwrap();
 
                 if more == 0 {
                     break;
@@ -224,109 +330,216 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     0
                 };
 
-                let messagee = string.as_str().unwrap();
-                // check type of request
-                let rest_of_message = if messagee.starts_with("WRITE") {
-                    &messagee[5..]
-                } else if messagee.starts_with("READ") {
-                    &messagee[4..]
-                } else if messagee.starts_with("REROUTE") {
-                    &messagee[7..]
-                } else {
-                    messagee
+                // answers go back in the encoding the request came in
+                let (request, encoding) = match protocol::decode_request(&string) {
+                    Ok(x) => x,
+                    Err(error) => {
+                        let response = protocol::encode_response(&error.into(), Encoding::detect(&string));
+                        server_responder.send(response, 0).unwrap();
+                        break;
+                    }
                 };
 
-                if messagee.starts_with("WRITE") {
-                    // create awset
-                    let mut awset = AWSet::new();
-                    let json_value: Value = serde_json::from_str(&rest_of_message).unwrap();
-                    awset.from_json(json_value);
-                    // save locally
-                    shopping_list.insert(awset.id.clone(), awset.clone());
-                    //write to local storage
-                    let _ = write_shopping_list_to_file(id, &shopping_list);
-                    server_responder.send("Received", 0).unwrap();
-                    break;
-
-                } else if messagee.starts_with("REROUTE") {
-                    // a server was found to be offline, this node is tasked with sending the
-                    // write to the offline node once its online
+                let response = match request {
+                    // heartbeats from the failure detectors, answered with our own digest
+                    Request::Gossip { digest } => {
+                        let mut detector = servers.detector.lock().unwrap();
+                        detector.merge(&digest);
+                        Response::Gossip { digest: detector.digest() }
+                    }
 
-                    let id_send = rest_of_message.chars().next().unwrap();
-                    let send_message = &rest_of_message[1..];
-                    let response =
-                        servers.send_to_worker(id_send.to_string(), send_message.to_string());
-                    server_responder.send(&response, 0).unwrap();
-                    break;
+                    Request::Join { id: new_id, port: new_port } => {
+                        // this node is the seed for a new server
+                        let old_ring = servers.membership.ring().clone();
+                        servers.membership.join(&new_id, &new_port);
+                        servers.save_membership();
+                        servers.sync_detector();
+                        info!("server {} joined on port {}", new_id, new_port);
+                        // answer first, the new server can't take handoffs until it has the view
+                        let response = Response::Members { members: servers.membership.view() };
+                        server_responder.send(protocol::encode_response(&response, encoding), 0).unwrap();
+                        servers.broadcast_members(id);
+                        hand_off_lists(&servers, id, &old_ring, &store);
+                        break;
+                    }
 
-                } else if messagee.starts_with("READ") {
-                    let list_owner_id = match get_owner_id(rest_of_message.to_string()) {
-                        Some(x) => x,
-                        None => {
-                            println!("Failed to retrieve owner");
-                            break;
+                    Request::Members { members } => {
+                        let old_ring = servers.membership.ring().clone();
+                        let change = servers.membership.apply(members);
+                        servers.save_membership();
+                        // servers that left are no longer gossiped with or about
+                        for removed in &change.removed {
+                            servers.detector.lock().unwrap().remove_peer(removed);
+                        }
+                        servers.sync_detector();
+                        server_responder.send(protocol::encode_response(&Response::Ack, encoding), 0).unwrap();
+                        if !change.is_empty() {
+                            info!("membership changed, joined: {:?}, left: {:?}", change.added, change.removed);
+                            hand_off_lists(&servers, id, &old_ring, &store);
                         }
-                    };
-                    // if node is the owner, send to other nodes 
-                    if list_owner_id == *id {
-                        let result: String = match dynamo_style_read(&servers, rest_of_message, id)
-                        {
-                            Ok(out) => out,
-                            Err(e) => e.to_string(),
-                        };
-                        server_responder.send(result.as_str(), 0).unwrap();
                         break;
                     }
-                    //else, read locally and respond
-                    let id_list = rest_of_message.trim();
-                    let shopping_lists = &shopping_list;
-
-                    if let Some(list) = shopping_lists.get(id_list) {
-                        let response = list.to_json().to_string();
-                        server_responder.send(&response, 0).unwrap();
-                    } else {
-                        // Handle case where the list ID does not exist
-                        server_responder.send("List not found", 0).unwrap();
+
+                    Request::Handoff { list } => {
+                        // a list this node became responsible for, merged with what we may already hold
+                        let mut awset = list;
+                        if let Some(local_awset) = store.get(&awset.id) {
+                            awset.merge(local_awset);
+                        }
+                        match write_list(&mut store, awset) {
+                            Ok(()) => Response::Ack,
+                            Err(error) => error.into(),
+                        }
+                    }
+
+                    Request::Leave => {
+                        // graceful leave: push every list to its replicas on the ring without us,
+                        // tell the others, then stop
+                        servers.membership.leave(id);
+                        servers.save_membership();
+                        for (key, awset) in store.iter() {
+                            let request = Request::Handoff { list: awset.clone() };
+                            for successor in servers.membership.ring().preference_list(key, servers.quorum.n) {
+                                let response = servers.send_to_worker(&successor, &request);
+                                if response != Response::Ack {
+                                    warn!("Failed to hand off list {} to server {}", key, successor);
+                                }
+                            }
+                        }
+                        servers.broadcast_members(id);
+                        if let Err(e) = store.flush() {
+                            warn!("Failed to checkpoint the lists: {}", e);
+                        }
+                        server_responder.send(protocol::encode_response(&Response::Ack, encoding), 0).unwrap();
+                        info!("left the cluster");
+                        return Ok(());
                     }
-                    break;
-                }
 
-                // case where message is a write
+                    Request::ReplicaWrite { list } => {
+                        // merged with our copy, which may hold writes another replica coordinated
+                        let mut awset = list;
+                        if let Some(local_awset) = store.get(&awset.id) {
+                            awset.merge(local_awset);
+                        }
+                        match write_list(&mut store, awset) {
+                            Ok(()) => Response::Ack,
+                            Err(error) => error.into(),
+                        }
+                    }
 
-                let json: Value = serde_json::from_str(rest_of_message).unwrap();
+                    Request::ReplicaDelta { delta } => {
+                        let mut list = store.get_or_new(&delta.id);
+                        if list.merge_delta(&delta) {
+                            match write_list(&mut store, list) {
+                                Ok(()) => Response::Ack,
+                                Err(error) => error.into(),
+                            }
+                        } else {
+                            ProtocolError::MissingDeltas { list_id: delta.id }.into()
+                        }
+                    }
 
-                let mut key = String::new();
-                if let Some(first_key) = get_first_key(&json) {
-                    key = first_key;
-                } else {
-                    println!("Not a Json file");
-                }
-                let list_id = &key;
-                //get the owner
-                let list_owner_id = match get_owner_id(list_id.to_string()) {
-                    Some(x) => x,
-                    None => {
-                        println!("Failed to retrieve owner");
-                        break;
+                    Request::ReplicaOps { list_id, ops } => {
+                        // operations are delivered in causal order, the ones that depend on
+                        // operations we don't have yet wait in the log
+                        let mut list = store.get_or_new(&list_id);
+                        let log = oplogs.log(&list_id);
+                        let delivered: usize = ops.iter().map(|op| log.receive(&mut list, op.clone())).sum();
+                        let stored = oplogs
+                            .append_ops(&list_id, &ops)
+                            .map_err(|e| ProtocolError::Storage { reason: format!("failed to store the op log: {}", e) })
+                            .and_then(|()| if delivered > 0 { write_list(&mut store, list) } else { Ok(()) });
+                        let log = oplogs.log(&list_id);
+                        if let Err(error) = stored {
+                            error.into()
+                        } else if log.pending().is_empty() {
+                            Response::Ack
+                        } else {
+                            ProtocolError::MissingOps { list_id: list_id.clone(), delivered: log.delivered().clone() }.into()
+                        }
+                    }
+
+                    Request::ReplicaSnapshot { list, delivered } => {
+                        // a replica too far behind the coordinator's log takes the whole list,
+                        // which has the effects of every op the coordinator delivered
+                        let mut awset = list;
+                        if let Some(local_awset) = store.get(&awset.id) {
+                            awset.merge(local_awset);
+                        }
+                        let list_id = awset.id.clone();
+                        oplogs.log(&list_id).fast_forward(&mut awset, &delivered);
+                        let stored = oplogs
+                            .append_delivered(&list_id, &delivered)
+                            .map_err(|e| ProtocolError::Storage { reason: format!("failed to store the op log: {}", e) })
+                            .and_then(|()| write_list(&mut store, awset));
+                        match stored {
+                            Ok(()) => Response::Ack,
+                            Err(error) => error.into(),
+                        }
+                    }
+
+                    Request::Hint { target, list } => {
+                        // a server was found to be offline, this node keeps the write as a hint
+                        // and delivers it once the failure detector sees the server again
+                        match servers.hints.lock().unwrap().add(&target, &list) {
+                            Ok(()) => Response::Ack,
+                            Err(e) => ProtocolError::Storage { reason: format!("failed to store hint: {}", e) }.into(),
+                        }
                     }
-                };
 
-                if &list_owner_id == id {
-                    let mut owner_awset = AWSet::new();
-                    owner_awset.from_json(json.clone());
-                    if let Some(local_awset) = shopping_list.get(&key) {
-                        owner_awset.merge(local_awset);
+                    Request::Merkle { peer, query } => {
+                        // anti-entropy: tree nodes over the keys shared with the peer, or local copies of lists
+                        match query {
+                            MerkleQuery::Lists { keys } => Response::Lists {
+                                lists: keys
+                                    .iter()
+                                    .filter_map(|key| store.get(key))
+                                    .cloned()
+                                    .collect(),
+                            },
+                            query => {
+                                let round_start = matches!(query, MerkleQuery::Nodes { level: 0, .. });
+                                if round_start || !merkle_trees.contains_key(&peer) {
+                                    let tree = shared_tree(&servers, id, &peer, &store, merkle_depth);
+                                    merkle_trees.insert(peer.clone(), tree);
+                                }
+                                merkle::answer(&merkle_trees[&peer], &query)
+                            }
+                        }
+                    }
+
+                    Request::Hints => {
+                        // admin view of the hints waiting for delivery
+                        Response::Hints { summary: servers.hints.lock().unwrap().summary() }
                     }
-                    shopping_list.insert(key.clone(), owner_awset.clone());
 
-                    let _ = write_shopping_list_to_file(id, &shopping_list);
+                    Request::Metrics => Response::Metrics { collected },
 
-                    let result: String = match send_to_other_nodes(&servers, &mut timeout_table, id, &owner_awset){
-                            Ok(out) => out,
-                            Err(e) => e.to_string(),
-                        };
-                        server_responder.send(result.as_str(), 0).unwrap();
-                }
+                    Request::ReplicaRead { list_id } => {
+                        // read locally and respond
+                        match store.get(list_id.trim()) {
+                            Some(list) => Response::List { list: list.clone(), acks: 1 },
+                            None => ProtocolError::NotFound { list_id }.into(),
+                        }
+                    }
+
+                    // client requests forwarded by the server that got them from the proxy;
+                    // any home replica of the list may coordinate them
+                    request @ (Request::Read { .. }
+                    | Request::Write { .. }
+                    | Request::WriteDelta { .. }
+                    | Request::Apply { .. }) => {
+                        let list_id = request.list_id().unwrap_or_default();
+                        let ring = servers.membership.ring();
+                        if ring.preference_list(&list_id, servers.quorum.n).contains(id) {
+                            handle_client_request(&servers, id, &mut store, &mut oplogs, request)
+                        } else {
+                            let owner = get_owner_id(ring, &list_id).unwrap_or_default();
+                            ProtocolError::NotOwner { owner }.into()
+                        }
+                    }
+                };
+                server_responder.send(protocol::encode_response(&response, encoding), 0).unwrap();
                 if more == 0 {
                     break;
                 }
@@ -335,233 +548,575 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 }
 
-fn get_first_key(json: &Value) -> Option<String> {
-    if let Value::Object(obj) = json {
-        return obj.keys().next().map(|k| k.to_string());
+// runs a client request with this server as the coordinator
+fn handle_client_request(
+    servers: &Servers,
+    server_id: &str,
+    store: &mut ListStore,
+    oplogs: &mut OpLogStore,
+    request: Request,
+) -> Response {
+    match request {
+        Request::Read { list_id, options } => {
+            let quorum = match servers.quorum.with_options(&options) {
+                Ok(x) => x,
+                Err(reason) => return ProtocolError::BadRequest { reason }.into(),
+            };
+            match dynamo_style_read(servers, list_id.trim(), server_id, store, &quorum) {
+                Ok((list, acks)) => Response::List { list, acks },
+                Err(error) => error.into(),
+            }
+        }
+        Request::Write { list, options } => {
+            let quorum = match servers.quorum.with_options(&options) {
+                Ok(x) => x,
+                Err(reason) => return ProtocolError::BadRequest { reason }.into(),
+            };
+            if list.id.is_empty() {
+                return ProtocolError::BadRequest { reason: "the list has no id".to_string() }.into();
+            }
+            let list_id = list.id.clone();
+            let mut owner_awset = list;
+            if let Some(local_awset) = store.get(&list_id) {
+                owner_awset.merge(local_awset);
+            }
+            // on disk before any replica acknowledges it
+            if let Err(error) = write_list(store, owner_awset.clone()) {
+                return error.into();
+            }
+
+            // the coordinator's own copy counts as the first acknowledgement
+            match send_to_other_nodes(servers, server_id, &owner_awset, Update::List, &quorum) {
+                Ok(outcome) => Response::Written { acks: outcome.acks, hinted: outcome.hinted },
+                Err(error) => error.into(),
+            }
+        }
+        Request::WriteDelta { delta, options } => {
+            let quorum = match servers.quorum.with_options(&options) {
+                Ok(x) => x,
+                Err(reason) => return ProtocolError::BadRequest { reason }.into(),
+            };
+            if delta.id.is_empty() {
+                return ProtocolError::BadRequest { reason: "the list has no id".to_string() }.into();
+            }
+            let mut owner_awset = store.get_or_new(&delta.id);
+            if !owner_awset.merge_delta(&delta) {
+                return ProtocolError::MissingDeltas { list_id: delta.id }.into();
+            }
+            if let Err(error) = write_list(store, owner_awset.clone()) {
+                return error.into();
+            }
+
+            match send_to_other_nodes(servers, server_id, &owner_awset, Update::Delta(&delta), &quorum) {
+                Ok(outcome) => Response::Written { acks: outcome.acks, hinted: outcome.hinted },
+                Err(error) => error.into(),
+            }
+        }
+        Request::Apply { list_id, operation, options } => {
+            let quorum = match servers.quorum.with_options(&options) {
+                Ok(x) => x,
+                Err(reason) => return ProtocolError::BadRequest { reason }.into(),
+            };
+            if list_id.is_empty() {
+                return ProtocolError::BadRequest { reason: "the list has no id".to_string() }.into();
+            }
+            let mut owner_awset = store.get_or_new(&list_id);
+            // the coordinator issues the operation, so its dots and counters are written
+            // by this server
+            let (op, item_id) = match oplogs.log(&list_id).issue(&mut owner_awset, server_id, operation) {
+                Ok(x) => x,
+                Err(error) => return ProtocolError::InvalidChange { error }.into(),
+            };
+            // the op is on disk before anything else sees its dot
+            if let Err(e) = oplogs.append_ops(&list_id, std::slice::from_ref(&op)) {
+                oplogs.log(&list_id).retract(&op);
+                return ProtocolError::Storage { reason: format!("failed to store the op log: {}", e) }.into();
+            }
+            if let Err(error) = write_list(store, owner_awset.clone()) {
+                return error.into();
+            }
+
+            let log = oplogs.log(&list_id);
+            match send_to_other_nodes(servers, server_id, &owner_awset, Update::Op(log, &op), &quorum) {
+                Ok(outcome) => Response::Applied { item_id, acks: outcome.acks, hinted: outcome.hinted },
+                Err(error) => error.into(),
+            }
+        }
+        request => ProtocolError::BadRequest { reason: format!("{:?} is not a client request", request) }.into(),
     }
-    None
 }
 
-// if we want to change the way to calculate the owner, we only need to change this function
-fn get_owner_id(list_id: String) -> Option<String> {
-    let list_id_int = match list_id.parse::<u32>() {
-        Ok(x) => x,
-        Err(e) => {
-            println!("List id not a number: {}", e);
-            return None;
+// delivers stored hints in the background once their target is alive again
+fn spawn_hint_delivery(hints: Arc<Mutex<HintQueue>>, detector: Arc<Mutex<FailureDetector>>, encoding: Encoding) {
+    thread::spawn(move || {
+        let context = zmq::Context::new();
+        loop {
+            thread::sleep(HINT_DELIVERY_INTERVAL);
+            let targets = {
+                let mut hints = hints.lock().unwrap();
+                match hints.expire() {
+                    Ok(0) => {}
+                    Ok(expired) => info!("Dropped {} expired hints", expired),
+                    Err(e) => warn!("Failed to expire hints: {}", e),
+                }
+                hints.targets()
+            };
+            for target in targets {
+                let port = {
+                    let detector = detector.lock().unwrap();
+                    if !detector.is_alive(&target) {
+                        continue;
+                    }
+                    match detector.port(&target) {
+                        Some(x) => x,
+                        None => continue,
+                    }
+                };
+                let pending = hints.lock().unwrap().pending(&target);
+                for hint in pending {
+                    let request = Request::Handoff { list: hint.awset.clone() };
+                    if send_to_port(&context, &port, &request, encoding) != Response::Ack {
+                        break;
+                    }
+                    info!("Delivered hinted list {} to server {}", hint.list_id, target);
+                    if let Err(e) = hints.lock().unwrap().remove_delivered(&hint) {
+                        warn!("Failed to remove delivered hint: {}", e);
+                    }
+                }
+            }
         }
-    };
-    let owner_int = list_id_int % 6;
-    let owner_id = format!("{}", owner_int);
-    Some(owner_id)
+    });
 }
 
-fn write_shopping_list_to_file(
+// merkle tree over the lists for which both this server and the peer are home replicas
+fn shared_tree(
+    servers: &Servers,
     server_id: &str,
-    shopping_list: &HashMap<String, AWSet>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let file_path = format!("public/data_{}.json", server_id);
-    let mut file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .open(&file_path)?;
-
-    let mut root = serde_json::Map::new();
-    for (key, awset) in shopping_list {
-        let json_value = awset.to_json();
-        root.insert(key.clone(), json_value[key].clone());
-    }
-
-    let json_data = serde_json::Value::Object(root);
-    file.write_all(serde_json::to_string_pretty(&json_data)?.as_bytes())?;
-    Ok(())
+    peer: &str,
+    store: &ListStore,
+    depth: u32,
+) -> MerkleTree {
+    let ring = servers.membership.ring();
+    let entries = store
+        .iter()
+        .filter(|(key, _)| {
+            let replicas = ring.preference_list(key, servers.quorum.n);
+            replicas.iter().any(|node| node == server_id) && replicas.iter().any(|node| node == peer)
+        })
+        .map(|(key, list)| (key.clone(), merkle::list_digest(list)));
+    MerkleTree::build(depth, entries)
 }
 
-fn send_to_other_nodes(
+// pushes every local list to the servers that became one of its home replicas
+// when the ring changed from old_ring to the current view
+fn hand_off_lists(
     servers: &Servers,
-    timeout_table: &mut TimeoutTable,
-    server_id: &String,
-    awset: &AWSet,
-) -> Result<String, &'static str> {
-    let server_count = servers.ports.len() as i32;
+    server_id: &str,
+    old_ring: &HashRing,
+    store: &ListStore,
+) {
+    for (key, awset) in store.iter() {
+        let targets = handoff_targets(old_ring, servers.membership.ring(), key, servers.quorum.n);
+        for target in targets.into_iter().filter(|node| node != server_id) {
+            let request = Request::Handoff { list: awset.clone() };
+            let response = servers.send_to_worker(&target, &request);
+            if response != Response::Ack {
+                warn!("Failed to hand off list {} to server {}", key, target);
+            }
+        }
+    }
+}
+
+// the server id names its files, and the ports given to --join must be ports
+fn validate_args(id: &str, join_args: Option<&(String, String)>) -> Result<(), String> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("Invalid server id {:?}, use letters, digits, '-' and '_'", id));
+    }
+    if let Some((port, seed_port)) = join_args {
+        for port in [port, seed_port] {
+            if port.parse::<u16>().is_err() {
+                return Err(format!("Invalid port {:?}", port));
+            }
+        }
+    }
+    Ok(())
+}
 
-    let id: i32 = server_id.parse().unwrap();
+// servers used to keep their files next to each other, in public/ and data/; they are moved
+// into the data directory the first time the server starts with one
+fn adopt_legacy_files(server_id: &str, data_dir: &Path, storage_config: &StorageConfig) {
+    let mut moves = vec![
+        (PathBuf::from(format!("data/hints_{}", server_id)), data_dir.join("hints")),
+        (PathBuf::from(format!("data/oplog_{}", server_id)), data_dir.join("oplog")),
+    ];
+    if storage_config.path.is_none() {
+        let legacy = match storage_config.engine {
+            StorageEngine::Json => format!("public/data_{}.json", server_id),
+            StorageEngine::Files => format!("data/lists_{}", server_id),
+            StorageEngine::Sled => format!("data/sled_{}", server_id),
+        };
+        moves.push((PathBuf::from(legacy), storage_config.engine.default_path(data_dir)));
+    }
+    if storage_config.log.is_none() {
+        moves.push((PathBuf::from(format!("data/wal_{}.log", server_id)), data_dir.join("wal.log")));
+    }
+    for (legacy, path) in moves {
+        if !legacy.exists() || path.exists() {
+            continue;
+        }
+        match fs::rename(&legacy, &path) {
+            Ok(()) => info!("moved {} to {}", legacy.display(), path.display()),
+            Err(e) => warn!("Failed to move {} to {}: {}", legacy.display(), path.display(), e),
+        }
+    }
+}
 
-    let json_string = awset.to_json().to_string();
+// if we want to change the way to calculate the owner, we only need to change this function
+fn get_owner_id(ring: &HashRing, list_id: &str) -> Option<String> {
+    let owner_id = ring.owner(list_id.trim());
+    if owner_id.is_none() {
+        warn!("No servers on the ring to own list {}", list_id);
+    }
+    owner_id
+}
 
-    let sent_message = "WRITE".to_owned() + &json_string;
+// keeps a changed list, written through to the storage engine; once this returns the write
+// is in the write-ahead log on disk and can be acknowledged
+fn write_list(store: &mut ListStore, list: AWSet) -> Result<(), ProtocolError> {
+    let list_id = list.id.clone();
+    store.put(list).map_err(|e| {
+        error!("Failed to store list {}: {}", list_id, e);
+        ProtocolError::Storage { reason: format!("failed to store list {}: {}", list_id, e) }
+    })
+}
 
-    let mut timeout_list = vec![];
+// copies of a write: `acks` counts every copy including ours, `hinted` the ones held by
+// a fallback server on behalf of a home replica that is down
+#[derive(Clone, Copy, Debug, Default)]
+struct WriteOutcome {
+    acks: usize,
+    hinted: usize,
+}
 
-    let n: i32 = 3;
+// what the home replicas are sent of a write, the whole list being the fallback for a
+// replica that can't take it
+enum Update<'a> {
+    List,
+    Delta(&'a AWSet),
+    // an operation, with the log it was issued in to catch up a replica that misses earlier ones
+    Op(&'a OpLog, &'a Op),
+}
 
-    let mut number: i32 = id + 1;
+// sloppy quorum: every home replica of the list gets its copy at once, each from its own
+// thread, and the write returns as soon as w of them acknowledged it; the others finish in
+// the background. The copy of a home replica that is down or doesn't take it goes as a hint
+// to the next live server past the first n, so the list is still on n servers, and those
+// hinted copies count toward w. It is kept as a hint here when no such server takes it.
+// When the write came as a delta, home replicas get the delta and only the ones that
+// missed earlier deltas get the whole list. An operation is followed by the operations
+// the replica is missing from our log, and by the whole list if that is not enough;
+// hints always hold the whole list.
+fn send_to_other_nodes(
+    servers: &Servers,
+    server_id: &str,
+    awset: &AWSet,
+    update: Update,
+    quorum: &QuorumConfig,
+) -> Result<WriteOutcome, ProtocolError> {
+    let sent_message = match update {
+        Update::List => Request::ReplicaWrite { list: awset.clone() },
+        Update::Delta(delta) => Request::ReplicaDelta { delta: delta.clone() },
+        Update::Op(_, op) => Request::ReplicaOps { list_id: awset.id.clone(), ops: vec![op.clone()] },
+    };
+    let log = match update {
+        Update::Op(log, _) => Some(Arc::new(log.clone())),
+        _ => None,
+    };
 
-    let timeout = Duration::from_secs(100);
+    let n = quorum.n;
+
+    // our own copy is the first one
+    let mut outcome = WriteOutcome { acks: 1, hinted: 0 };
+
+    // every server in ring order for this list, the first n are its home replicas
+    let ring = servers.membership.ring();
+    let preference_list = ring.preference_list(&awset.id, ring.len());
+
+    // the live servers past the home replicas, in ring order, each holding a hinted copy
+    // for one home replica that didn't take the write
+    let fallbacks: VecDeque<(String, String)> = preference_list
+        .iter()
+        .skip(n)
+        .filter(|node| node.as_str() != server_id && servers.is_alive(node))
+        .filter_map(|node| servers.membership.port(node).map(|port| (node.clone(), port.clone())))
+        .collect();
+    let fallbacks = Arc::new(Mutex::new(fallbacks));
+
+    let (sender, receiver) = mpsc::channel();
+    let mut sent = 0;
+    for replica in preference_list.iter().take(n).filter(|node| node.as_str() != server_id) {
+        // servers the failure detector doesn't see as alive are skipped without a round trip
+        let port = servers.membership.port(replica).filter(|_| servers.is_alive(replica)).cloned();
+        let copy = ReplicaCopy {
+            replica: replica.clone(),
+            port,
+            request: sent_message.clone(),
+            awset: awset.clone(),
+            log: log.clone(),
+        };
+        let context = servers.context.clone();
+        let (encoding, detector, hints) = (servers.encoding, servers.detector.clone(), servers.hints.clone());
+        let (fallbacks, sender) = (fallbacks.clone(), sender.clone());
+        // every copy is sent, or handed to a fallback server, even after the write returned
+        thread::spawn(move || {
+            let delivery = copy.send(&context, encoding, &detector, &hints, &fallbacks);
+            let _ = sender.send(delivery);
+        });
+        sent += 1;
+    }
+    drop(sender);
 
-    let mut success_send: i32 = 0;
-    let server_list = (0..server_count)
-        .cycle()
-        .skip((id + 1) as usize)
-        .take((n - 1) as usize)
-        .collect::<Vec<_>>();
-        
-
-    while success_send < (n - 1) && (number) != id {
-        if number >= servers.ports.len() as i32 {
-            number = 0;
-        }
-        // println!("NUMBER: {}", number);
-        // println!("ID, {}", id);
-        // println!("SUCC, {}", success_send);
-        if server_list.contains(&number)
-            && !timeout_table.is_timed_out(&number.to_string(), timeout){   
-            let result = servers.send_to_worker(number.to_string(), sent_message.clone());
-            println!("SENT TO, {}", result.as_str());
-            let match_result = result.as_str();
-
-            match match_result {
-                "Received" => { success_send += 1; }
-                _ => {
-                    println!("Error sending to worker, getting in timeout");
-                    timeout_table.update_timestamp(&number.to_string());
-                }
+    for _ in 0..sent {
+        if outcome.acks >= quorum.w {
+            break;
+        }
+        match receiver.recv() {
+            Ok(Delivery::Acked) => outcome.acks += 1,
+            Ok(Delivery::Hinted) => {
+                outcome.acks += 1;
+                outcome.hinted += 1;
             }
-        } else if server_list.contains(&number) {
-            timeout_list.push(number);
-        } else {
-            if !timeout_table.is_timed_out(&number.to_string(), timeout) {
-                if !timeout_list.is_empty() {
-                    let real_node = timeout_list.remove(0);
-
-                    let message = format!("REROUTE{}", real_node.to_string());
+            Ok(Delivery::Failed) => {}
+            Err(_) => break,
+        }
+    }
 
-                    let final_message = message + &sent_message;
+    if outcome.acks < quorum.w {
+        warn!("Error no servers available");
+        return Err(ProtocolError::QuorumFailed { needed: quorum.w, acks: outcome.acks, hinted: outcome.hinted });
+    }
+    Ok(outcome)
+}
 
-                    let result = servers.send_to_worker(number.to_string(), final_message);
+// where the copy of a write for one home replica ended up
+enum Delivery {
+    Acked,
+    // held by a fallback server until the replica is back
+    Hinted,
+    // only kept as a hint by this server
+    Failed,
+}
 
-                    let match_result = result.as_str();
+// the copy of a write going to one home replica
+struct ReplicaCopy {
+    replica: String,
+    // None for a replica the failure detector doesn't see as alive
+    port: Option<String>,
+    request: Request,
+    awset: AWSet,
+    // the log of an operation, to catch up a replica that misses earlier ones
+    log: Option<Arc<OpLog>>,
+}
 
-                    match match_result {
-                        "Received" => {
-                            success_send += 1;
-                        }
-                        _ => {
-                            println!("Error sending to worker, getting in timeout");
-                            timeout_table.update_timestamp(&number.to_string());
-                        }
+impl ReplicaCopy {
+    // sends the copy, falling back to the missing operations and then the whole list. A
+    // copy the replica didn't take goes to the next fallback server as a hint, or is kept
+    // as a hint here when none takes it
+    fn send(
+        &self,
+        context: &zmq::Context,
+        encoding: Encoding,
+        detector: &Mutex<FailureDetector>,
+        hints: &Mutex<HintQueue>,
+        fallbacks: &Mutex<VecDeque<(String, String)>>,
+    ) -> Delivery {
+        if let Some(port) = &self.port {
+            match self.send_to_replica(context, port, encoding) {
+                Response::Ack => return Delivery::Acked,
+                result => {
+                    warn!("Error sending to worker {}", self.replica);
+                    if let Response::Error { error: ProtocolError::Unavailable { .. } } = result {
+                        detector.lock().unwrap().report_failure(&self.replica);
+                    }
+                }
+            }
+        }
+        let hint = Request::Hint { target: self.replica.clone(), list: self.awset.clone() };
+        loop {
+            let next = fallbacks.lock().unwrap().pop_front();
+            let (fallback, port) = match next {
+                Some(x) => x,
+                None => break,
+            };
+            match send_to_port(context, &port, &hint, encoding) {
+                Response::Ack => {
+                    debug!("Server {} holds a hint for {}", fallback, self.replica);
+                    return Delivery::Hinted;
+                }
+                result => {
+                    warn!("Error sending to worker {}", fallback);
+                    if let Response::Error { error: ProtocolError::Unavailable { .. } } = result {
+                        detector.lock().unwrap().report_failure(&fallback);
                     }
                 }
             }
         }
-        number += 1;
+        if let Err(e) = hints.lock().unwrap().add(&self.replica, &self.awset) {
+            warn!("Failed to store a hint for server {}: {}", self.replica, e);
+        }
+        Delivery::Failed
     }
 
-    if success_send < 2 {
-        println!("Error no servers available");
-        return Err("Not enough successes");
+    fn send_to_replica(&self, context: &zmq::Context, port: &str, encoding: Encoding) -> Response {
+        let mut result = send_to_port(context, port, &self.request, encoding);
+        if let (Some(log), Response::Error { error: ProtocolError::MissingOps { delivered, .. } }) = (&self.log, &result) {
+            if log.can_catch_up(delivered) {
+                debug!("Server {} is missing operations of list {}, sending them", self.replica, self.awset.id);
+                let ops = log.since(delivered);
+                let request = Request::ReplicaOps { list_id: self.awset.id.clone(), ops };
+                result = send_to_port(context, port, &request, encoding);
+            }
+        }
+        if let Response::Error { error: ProtocolError::MissingDeltas { .. } | ProtocolError::MissingOps { .. } } = result {
+            debug!("Server {} is behind on list {}, sending the whole list", self.replica, self.awset.id);
+            let request = match &self.log {
+                Some(log) => Request::ReplicaSnapshot { list: self.awset.clone(), delivered: log.delivered().clone() },
+                None => Request::ReplicaWrite { list: self.awset.clone() },
+            };
+            result = send_to_port(context, port, &request, encoding);
+        }
+        debug!("SENT TO {}, {:?}", self.replica, result);
+        result
     }
-    Ok("Success".to_string())
 }
 
-fn load_shopping_list(my_id: String) -> HashMap<String, AWSet> {
-    let data_location = format!("public/data_{}.json", my_id);
-    let data_content = match fs::read_to_string(data_location) {
-        Ok(x) => x,
+// rewrites a data file holding lists of an older schema version in the current one. The
+// lists from before the version field could keep removed items as tombstones, they are
+// already gone from the loaded lists so the rewrite purges them
+fn migrate_data_file(data_file: &Path, store: &mut ListStore) -> usize {
+    let stored = match fs::read_to_string(data_file)
+        .ok()
+        .and_then(|contents| serde_json::from_str::<Value>(&contents).ok())
+    {
+        Some(x) => x,
+        None => return 0,
+    };
+    let outdated = match &stored {
+        Value::Object(_) => true,
+        Value::Array(lists) => lists.iter().any(|list| list["version"].as_u64() != Some(SCHEMA_VERSION as u64)),
+        _ => false,
+    };
+    if !outdated {
+        return 0;
+    }
+    let tombstones = AWSet::tombstones(&stored);
+    match store.rewrite() {
+        Ok(()) => {
+            info!("migrated the lists to schema version {}, collected {} tombstones", SCHEMA_VERSION, tombstones);
+            tombstones
+        }
         Err(e) => {
-            println!("{}", e);
-            panic!()
+            warn!("Failed to migrate the lists: {}", e);
+            0
         }
-    };
+    }
+}
 
-    let json: Value = match serde_json::from_str(&data_content) {
-        Ok(value) => value,
-        Err(e) => {
-            eprintln!("Error parsing ports JSON: {:?}", e);
-            panic!();
+// hands a client request for a list this server isn't a home replica of to the first
+// home replica the failure detector sees alive, and to the next one when it doesn't answer
+fn forward_to_replica(servers: &Servers, replicas: &[String], request: &Request) -> Response {
+    for replica in replicas.iter().filter(|replica| servers.is_alive(replica)) {
+        debug!("I am not a replica of the list, sending to server {}", replica);
+        match servers.send_to_worker(replica, request) {
+            Response::Error { error: ProtocolError::Unavailable { .. } } => continue,
+            response => return response,
         }
-    };
-    let mut shopping_lists: HashMap<String, AWSet> = HashMap::new();
-    let lists = match json.as_object() {
-        Some(x) => x,
-        None => panic!("Oh no"),
-    };
-    for list_id in lists.keys() {
-        let mut new_awset = AWSet::new();
-        let new_json = json!({list_id:json[list_id]});
-        new_awset.from_json(new_json.clone());
-        shopping_lists.insert(list_id.to_owned(), new_awset);
     }
-    shopping_lists
+    ProtocolError::Unavailable { server: replicas[0].clone() }.into()
 }
 
-fn dynamo_style_read(servers: &Servers, key: &str, server_id: &String) -> Result<String, &'static str> {
-    let mut responses: Vec<AWSet> = Vec::new();
-    let server_count = servers.ports.len() as i32;
-    let mut repair_list: Vec<i32> = Vec::new();
-    let id: i32 = server_id.parse().unwrap();
-    let worker_lists = load_shopping_list(server_id.to_string());
-    let mut worker_list = match worker_lists.get(key){
-        Some(x) => x.clone(),
-        None => return Ok("NONE".to_string())
-    };
-    let quorum: i32 = 2;
-    let n: i32 = 3;
-    let replicas = (0..server_count)
-        .cycle()
-        .skip((id + 1) as usize)
-        .take((n - 1) as usize)
+// reads the list from up to r replicas (counting our own copy), repairs the ones that
+// were behind and answers with the merged list and how many replicas it was read from.
+// A replica without the list answers too; until one of them has it the other replicas
+// are asked as well, and the list is only NotFound when none of them has it
+fn dynamo_style_read(
+    servers: &Servers,
+    key: &str,
+    server_id: &str,
+    store: &mut ListStore,
+    quorum: &QuorumConfig,
+) -> Result<(AWSet, usize), ProtocolError> {
+    let mut responses: Vec<Option<AWSet>> = Vec::new();
+    let mut repair_list: Vec<String> = Vec::new();
+    let mut worker_list = store.get(key).cloned();
+    let n = quorum.n;
+    let replicas = servers
+        .membership
+        .ring()
+        .preference_list(key, n)
+        .into_iter()
+        .filter(|node| node != server_id && servers.is_alive(node))
         .collect::<Vec<_>>();
-    let mut successful_reads = 0;
+    let mut successful_reads: usize = 1;
     for replica in replicas {
-        let read_message = format!("READ{}", key);
-        match servers.send_to_worker(replica.to_string().clone(), read_message) {
-            response if !response.is_empty() => {
-                // Parse the response as JSON
-                if let Ok(json) = serde_json::from_str::<Value>(&response) {
-                    let mut awset = AWSet::new();
-                    awset.from_json(json);
-                    responses.push(awset.clone());
-                    repair_list.push(replica);
-                    worker_list.merge(&awset);
-                    successful_reads += 1;
-
-                    // Stop early if quorum is met
-                    if successful_reads >= quorum {
-                        break;
-                    }
-                } else {
-                    println!("Error parsing JSON from replica {}", replica);
-                }
+        if successful_reads >= quorum.r && worker_list.is_some() {
+            break;
+        }
+        let read_message = Request::ReplicaRead { list_id: key.to_string() };
+        let awset = match servers.send_to_worker(&replica, &read_message) {
+            Response::List { list, .. } => list,
+            Response::Error { error: ProtocolError::NotFound { .. } } => {
+                responses.push(None);
+                repair_list.push(replica);
+                successful_reads += 1;
+                continue;
             }
-            _ => {
-                println!("Failed to read from replica {}", replica);
+            response => {
+                warn!("Failed to read from replica {}: {:?}", replica, response);
+                continue;
             }
+        };
+        // a copy we already have all of adds nothing, one that has all of ours replaces it
+        match &mut worker_list {
+            None => worker_list = Some(awset.clone()),
+            Some(list) => match awset.compare(list) {
+                CausalOrder::Before | CausalOrder::Equal => {}
+                CausalOrder::After => *list = awset.clone(),
+                CausalOrder::Concurrent => list.merge(&awset),
+            },
         }
+        responses.push(Some(awset));
+        repair_list.push(replica);
+        successful_reads += 1;
     }
+    let worker_list = match worker_list {
+        Some(x) => x,
+        None => return Err(ProtocolError::NotFound { list_id: key.to_string() }),
+    };
 
-    // Update the local shopping list with the merged result
-    let mut shopping_lists = load_shopping_list(server_id.to_string());
-    shopping_lists.insert(key.to_string(), worker_list.clone());
-    let _ = write_shopping_list_to_file(server_id, &shopping_lists);
+    // our own copy is repaired like the others, only when a replica had writes we missed
+    if store.get(key).is_none_or(|local| local.compare(&worker_list) != CausalOrder::Equal) {
+        if let Err(error) = write_list(store, worker_list.clone()) {
+            warn!("Read repair of the local copy failed: {}", error);
+        }
+    }
 
-    // Repair replicas if needed
-    for i in 0..repair_list.len() {
-        if worker_list != responses[i] {
-            let json_string = worker_list.to_json().to_string();
-            let write_message = format!("WRITE{}", json_string);
-            let response = servers.send_to_worker(repair_list[i].to_string(), write_message);
-            if response != "Received" {
-                println!("Failed to repair replica {}", repair_list[i]);
+    // Repair the replicas that missed writes, the merged list includes every copy
+    for (replica, response) in repair_list.iter().zip(&responses) {
+        if response.as_ref().is_none_or(|list| list.compare(&worker_list) != CausalOrder::Equal) {
+            let write_message = Request::ReplicaWrite { list: worker_list.clone() };
+            let response = servers.send_to_worker(replica, &write_message);
+            if response != Response::Ack {
+                warn!("Failed to repair replica {}", replica);
             }
         }
     }
-    let string_aw = worker_list.to_json().to_string();
     // Check quorum
-    if successful_reads >= quorum {
-        return Ok(string_aw); // Return the entire shopping list as a string
+    if successful_reads >= quorum.r {
+        // Return the entire shopping list
+        Ok((worker_list, successful_reads))
     } else {
-        println!("reads succ {}", successful_reads);
-        return Err("Not enough successful responses");
+        debug!("reads succ {}", successful_reads);
+        Err(ProtocolError::QuorumFailed { needed: quorum.r, acks: successful_reads, hinted: 0 })
     }
 }
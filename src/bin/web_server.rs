@@ -1,208 +1,381 @@
+use actix_web::http::StatusCode;
 use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder};
 use actix_cors::Cors;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use slde::crdt::AWSet;
+use serde_json::{json, Value};
+use slde::config::{self, WebServerConfig};
+use slde::crdt::{AWSet, CrdtError, Quantity};
+use slde::oplog::Operation;
+use slde::protocol::{self, Encoding, ProtocolError, Replication, Request, Response};
+use slde::quorum::RequestOptions;
+use slde::{debug, info, log, warn};
 use uuid::Uuid;
+use std::env;
 use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
 use std::io::Write;
 
 #[derive(Serialize, Deserialize)]
 struct Change {
     r#type: String,
     list_id: String,
-    item_name: String,
+    // the item a change is about, all but add have it
+    item_id: Option<String>,
+    // the name of an added or renamed item
+    item_name: Option<String>,
     target: Option<u64>,
     bought: Option<u64>,
-    replica: String
+    // the counter and the amount of an increment or decrement
+    quantity: Option<Quantity>,
+    amount: Option<u64>,
+    replica: String,
+    // per-request write quorum, the cluster default when missing
+    w: Option<usize>
 }
 
+#[derive(Deserialize)]
+struct ReadQuery {
+    // per-request read quorum, the cluster default when missing
+    r: Option<usize>
+}
 
+// how many replicas answered the request
+const ACKS_HEADER: &str = "X-Replica-Acks";
 
-#[derive(Serialize, Deserialize)]
-struct Changes {
-    changes: Vec<Change>,
+// the configuration, with the settings read from data/ resolved at startup
+struct Settings {
+    proxy: String,
+    // the list last read or changed through this web server
+    cache_file: PathBuf,
+    quorum: RequestOptions,
+    // the encoding of the messages sent to the servers
+    encoding: Encoding,
+    // how changes are sent to the servers
+    replication: Replication,
 }
 
+
 #[get("/generate_id")]
 async fn generate_id() -> impl Responder {
-    let user_id = Uuid::new_v4().to_string();
-    user_id
+    Uuid::new_v4().to_string()
 }
 
 #[get("/list.json/{id}")]
-async fn get_list(id: web::Path<String>) -> impl Responder {
-    println!("Looking for the list");
-    // Read the contents of the JSON file
-    let contents = match fs::read_to_string("public/list.json") {
-        Ok(x) => x,
-        Err(_e) => {
-            read_from_servers(id.to_string())
-        }
-    };
-    println!("{}",contents);
-
-    
-
-    // Parse the JSON contents
-    let json: Value = match serde_json::from_str(&contents) {
-        Ok(value) => value,
-        Err(e) => {
-            return HttpResponse::InternalServerError().body(format!("Error parsing JSON: {}", e));
-        }
-    };
-
-    let correct_list = match json.get(id.to_string()){
-        Some(_x) => true,
-        None => false
+async fn get_list(id: web::Path<String>, query: web::Query<ReadQuery>, settings: web::Data<Settings>) -> impl Responder {
+    debug!("Looking for the list");
+    let options = RequestOptions { r: query.r.or(settings.quorum.r), w: None };
+    // Read the cached list, asking for a specific read quorum always goes to the servers
+    let cached = if query.r.is_none() {
+        read_cached_list(&settings).ok()
+    } else {
+        None
     };
 
-    if correct_list{
-        return HttpResponse::Ok().json(json);
-    }else{
-        println!("incorrect list");
-        read_from_servers(id.to_string());
-        let contents = fs::read_to_string("public/list.json").unwrap();
-
-        let json: Value = match serde_json::from_str(&contents) {
-            Ok(value) => value,
-            Err(e) => {
-                return HttpResponse::InternalServerError().body(format!("Error parsing JSON: {}", e));
-            }
-        };
-        return HttpResponse::Ok().json(json)
+    if let Some(list) = cached.filter(|list| list.id == id.as_str()) {
+        return HttpResponse::Ok().json(list_view(&list));
     }
 
-    // Return an error if the list ID is not found or "s" is not available
-    //HttpResponse::NotFound().body("No list associated with this ID.")
+    debug!("reading the list from the servers");
+    match read_from_servers(&settings, id.to_string(), options) {
+        Ok((list, acks)) => HttpResponse::Ok()
+            .insert_header((ACKS_HEADER, acks.to_string()))
+            .json(list_view(&list)),
+        Err(e) => HttpResponse::ServiceUnavailable().body(e),
+    }
 }
 
 #[post("/changes")]
-async fn add_change(change: web::Json<Change>) -> impl Responder {
+async fn add_change(change: web::Json<Change>, settings: web::Data<Settings>) -> impl Responder {
+    if change.list_id.is_empty() {
+        return HttpResponse::BadRequest().body("missing \"list_id\" in change");
+    }
 
-    let contents = match fs::read_to_string("public/list.json"){
-        Ok(x) => {
-            println!("{}",x);
-            x
-        },
-        Err(e) => {
-            panic!("{}",e)
-        }
+    let operation = match change_operation(&change) {
+        Ok(x) => x,
+        Err(response) => return response,
     };
-    let json: Value = match serde_json::from_str(&contents) {
-        Ok(value) => value,
-        Err(e) => {
-            eprintln!("Error parsing ports JSON: {:?}", e);
-            panic!();
+
+    let options = RequestOptions { r: None, w: change.w.or(settings.quorum.w) };
+    // the list the change is about as the servers hold it, the cache may be another list
+    let read_list = || {
+        read_from_servers(&settings, change.list_id.clone(), RequestOptions { r: settings.quorum.r, w: None })
+            .map(|(list, _)| list)
+            .map_err(|e| HttpResponse::ServiceUnavailable().body(format!("Error reading the list: {}", e)))
+    };
+    let (result, item_id) = match settings.replication {
+        // the coordinator runs the operation and broadcasts it to the replicas
+        Replication::Ops => {
+            let request = Request::Apply { list_id: change.list_id.clone(), operation, options };
+            match write_to_servers(&settings, request) {
+                Response::Error { error: ProtocolError::InvalidChange { error } } => return crdt_error(error),
+                response => {
+                    let item_id = match &response {
+                        Response::Applied { item_id, .. } => {
+                            // the cached list doesn't have the operation yet
+                            let _ = read_from_servers(&settings, change.list_id.clone(), RequestOptions::default());
+                            item_id.clone()
+                        }
+                        _ => None,
+                    };
+                    (response, item_id)
+                }
+            }
+        }
+        Replication::State => {
+            let mut shopping_list = match read_list() {
+                Ok(x) => x,
+                Err(response) => return response,
+            };
+            let (delta, item_id) = match operation.apply(&mut shopping_list, &change.replica) {
+                Ok(x) => x,
+                Err(error) => return crdt_error(error),
+            };
+            cache_list(&settings, &shopping_list);
+            // only the change is sent, unless the servers missed earlier changes to the list
+            let result = match write_to_servers(&settings, Request::WriteDelta { delta, options }) {
+                Response::Error { error: ProtocolError::MissingDeltas { .. } } => {
+                    info!("the servers are behind, sending the whole list");
+                    write_to_servers(&settings, Request::Write { list: shopping_list, options })
+                }
+                response => response,
+            };
+            (result, item_id)
         }
     };
+    debug!("DID IT CHANGE THE SERVER? {:?}", result);
+    let acks = match &result {
+        Response::Written { acks, .. } | Response::Applied { acks, .. } => *acks,
+        Response::Error { error: ProtocolError::QuorumFailed { acks, .. } } => *acks,
+        _ => 0,
+    };
+    let mut response = match &result {
+        Response::Written { .. } | Response::Applied { .. } => HttpResponse::Ok(),
+        Response::Error { error } => HttpResponse::build(error_status(error)),
+        _ => HttpResponse::BadGateway(),
+    };
+    let message = match &result {
+        Response::Written { .. } | Response::Applied { .. } => "Change added successfully".to_string(),
+        Response::Error { error } => error.to_string(),
+        response => format!("Unexpected response: {:?}", response),
+    };
+    let mut body = json!({"result": message, "servers": result});
+    if let Some(item_id) = item_id {
+        body["item_id"] = json!(item_id);
+    }
+    response
+        .insert_header((ACKS_HEADER, acks.to_string()))
+        .json(body)
+}
 
-    
-
-    let mut shopping_list = AWSet::new();
-    shopping_list.from_json(json.clone());
+// the status of a change the servers refused
+fn error_status(error: &ProtocolError) -> StatusCode {
+    match error {
+        ProtocolError::VersionMismatch { .. } | ProtocolError::BadRequest { .. } => StatusCode::BAD_REQUEST,
+        ProtocolError::NotFound { .. } => StatusCode::NOT_FOUND,
+        ProtocolError::InvalidChange { error: CrdtError::NotPresent { .. } } => StatusCode::NOT_FOUND,
+        // the servers are behind the cached list
+        ProtocolError::MissingDeltas { .. } | ProtocolError::MissingOps { .. } => StatusCode::CONFLICT,
+        ProtocolError::NotOwner { .. } => StatusCode::BAD_GATEWAY,
+        ProtocolError::QuorumFailed { .. } | ProtocolError::Unavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+        ProtocolError::InvalidChange { .. } | ProtocolError::Storage { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
 
-    match change.r#type.as_str(){
+// the operation of a change, a bad request for an unknown change type
+fn change_operation(change: &Change) -> Result<Operation, HttpResponse> {
+    let missing = |field: &str| {
+        HttpResponse::BadRequest().body(format!("missing \"{}\" in {} change", field, change.r#type))
+    };
+    let item_id = || change.item_id.clone().ok_or_else(|| missing("item_id"));
+    let name = || change.item_name.clone().ok_or_else(|| missing("item_name"));
+    let target = || change.target.ok_or_else(|| missing("target"));
+    let bought = || change.bought.ok_or_else(|| missing("bought"));
+    let quantity = || change.quantity.ok_or_else(|| missing("quantity"));
+    let amount = || change.amount.ok_or_else(|| missing("amount"));
+    let operation = match change.r#type.as_str(){
         "add" =>{
-            println!("recieved an add request");
-            shopping_list.add(&change.item_name, change.target.unwrap(), change.bought.unwrap(), &change.replica, false);
+            debug!("recieved an add request");
+            Operation::Add { name: name()?, target: target()?, bought: bought()? }
         },
         "remove" =>{
-            println!("recieved a remove request");
-            shopping_list.remove(&change.item_name, &change.replica);
+            debug!("recieved a remove request");
+            Operation::Remove { item_id: item_id()? }
+        },
+        "rename" =>{
+            debug!("recieved a rename request");
+            Operation::Rename { item_id: item_id()?, name: name()? }
         },
         "update" =>{
-            println!("recieved an update request");
-            shopping_list.update_item_amounts(&change.item_name, change.target.unwrap(), change.bought.unwrap(), &change.replica);
+            debug!("recieved an update request");
+            Operation::Update { item_id: item_id()?, target: target()?, bought: bought()? }
+        },
+        "increment" =>{
+            debug!("recieved an increment request");
+            Operation::Increment { item_id: item_id()?, quantity: quantity()?, amount: amount()? }
+        },
+        "decrement" =>{
+            debug!("recieved a decrement request");
+            Operation::Decrement { item_id: item_id()?, quantity: quantity()?, amount: amount()? }
+        },
+        _=> {
+            warn!("invalid change type");
+            return Err(HttpResponse::BadRequest().body(format!("unknown change type {:?}", change.r#type)));
+        }
+    };
+    Ok(operation)
+}
 
-        }, 
-        _=> println!("invalid change type")
+fn crdt_error(error: CrdtError) -> HttpResponse {
+    match error {
+        // the item was removed by someone else since the client read the list
+        CrdtError::NotPresent { .. } => HttpResponse::NotFound().body(error.to_string()),
+        CrdtError::Malformed { .. } => HttpResponse::InternalServerError().body(error.to_string()),
     }
+}
+
+// returns the list and how many replicas it was read from
+fn read_from_servers(settings: &Settings, list_id: String, options: RequestOptions) -> Result<(AWSet, usize), String> {
+    let context = zmq::Context::new();
+
+    let requester = context.socket(zmq::REQ).unwrap();
+    assert!(requester.connect(&settings.proxy).is_ok());
 
-    let changed_json = shopping_list.to_json();
-    let json_as_string = serde_json::to_string_pretty(&changed_json).unwrap();
+    let request = Request::Read { list_id: list_id.clone(), options };
+    debug!("requesting: {:?}", request);
+    requester.send(protocol::encode_request(&request, settings.encoding), 0).unwrap();
+    debug!("Request sent!");
+
+    let response_bytes = requester.recv_bytes(0).unwrap();
+    let response = protocol::decode_response(&response_bytes);
+    debug!("Response:\n{:?}", response);
+
+    let (list, acks) = match response {
+        Response::List { list, acks } => (Some(list), acks),
+        Response::Error { error: ProtocolError::NotFound { .. } } => (None, 0),
+        Response::Error { error } => return Err(error.to_string()),
+        response => return Err(format!("Unexpected response: {:?}", response)),
+    };
+
+    let list = match list {
+        Some(x) => x,
+        None => {
+            // list doesnt exist on server
+            info!("list doesnt exist on server");
+            let mut list = AWSet::new();
+            list.set_id(list_id);
+            list
+        }
+    };
+    cache_list(settings, &list);
+    Ok((list, acks))
+}
+
+// the list last read or changed through this web server, kept in list.json in its data directory
+fn read_cached_list(settings: &Settings) -> Result<AWSet, String> {
+    let contents = fs::read_to_string(&settings.cache_file).map_err(|e| e.to_string())?;
+    let json: Value = serde_json::from_str(&contents).map_err(|e| e.to_string())?;
+    AWSet::migrate(json).map_err(|e| e.to_string())
+}
 
+fn cache_list(settings: &Settings, list: &AWSet) {
     let mut file = OpenOptions::new()
         .write(true)
         .truncate(true)
         .create(true)
-        .open("public/list.json").unwrap();
-    file.write_all(json_as_string.as_bytes()).unwrap();
-    let result = write_to_servers();
-    println!("DID IT CHANGE THE SERVER? {}", result);
-    "Change added successfully"
+        .open(&settings.cache_file).unwrap();
+    file.write_all(serde_json::to_string_pretty(list).unwrap().as_bytes()).unwrap();
 }
 
-fn read_from_servers(list_id: String)->String{
-    let context = zmq::Context::new();
-
-    let requester = context.socket(zmq::REQ).unwrap();
-    assert!(requester.connect("tcp://localhost:5559").is_ok());
-
-    let contents = format!("READ{}",list_id);
-    println!("requesting: {}", contents);
-    requester.send(contents.as_str(), 0).unwrap();
-    println!("Request sent!");
-
-    let response_string = requester.recv_string(0).unwrap().unwrap();
-    println!("Response:\n{}",response_string);
-
-    if response_string =="NONE" {
-        // list doesnt exist on server
-        println!("list doesnt exist on server");
-        let json_data = r#"
-{
-    "list_id": {
-        "s": [
-        ],
-        "c": [
-        ]
-    }
-}
-        "#;
-        let final_json_data = json_data.replace("list_id", &list_id);
-        let mut file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .create(true)
-            .open("public/list.json").unwrap();
-        file.write_all(final_json_data.clone().as_bytes()).unwrap();
-        return final_json_data;
-    }else{
-        let mut file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .create(true)
-            .open("public/list.json").unwrap();
-        file.write_all(response_string.as_bytes()).unwrap();
-        return response_string.to_string();
-    }
+// what the browser shows: the current amounts of every item, without the causal metadata.
+// An item edited concurrently has several names or siblings, each with the replica that
+// wrote it, for the user to pick one
+fn list_view(list: &AWSet) -> Value {
+    let items: Vec<Value> = list
+        .elements()
+        .into_iter()
+        .map(|(item_id, item)| {
+            let mut view = json!({
+                "id": item_id,
+                "names": item.name.values(),
+                "target": item.target.value(),
+                "bought": item.bought.value()
+            });
+            // items from before the modification time was kept don't have one
+            if item.modified.wall > 0 {
+                view["modified"] = json!({"at": item.modified.wall, "by": item.modified.replica});
+            }
+            let siblings = list.siblings(item_id);
+            if siblings.len() > 1 {
+                view["siblings"] = json!(siblings
+                    .iter()
+                    .map(|sibling| json!({
+                        "replica": sibling.dot.replica,
+                        "target": sibling.value.target.value(),
+                        "bought": sibling.value.bought.value()
+                    }))
+                    .collect::<Vec<_>>());
+            }
+            view
+        })
+        .collect();
+    json!({"id": list.id, "items": items})
 }
 
-fn write_to_servers() -> String{
+fn write_to_servers(settings: &Settings, request: Request) -> Response {
     let context = zmq::Context::new();
 
     let requester = context.socket(zmq::REQ).unwrap();
-    assert!(requester.connect("tcp://localhost:5559").is_ok());
+    assert!(requester.connect(&settings.proxy).is_ok());
 
-    let contents = fs::read_to_string("public/list.json").unwrap();
-    requester.send(contents.as_str(), 0).unwrap();
-    println!("Request sent!");
-    let string = requester.recv_msg(0).unwrap();
-    return string.as_str().unwrap().to_string()
+    requester.send(protocol::encode_request(&request, settings.encoding), 0).unwrap();
+    debug!("Request sent!");
+    let bytes = requester.recv_bytes(0).unwrap();
+    protocol::decode_response(&bytes)
 }
 
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    HttpServer::new(|| {
+    // web_server [--config <path>] [--<key> <value> ...]
+    let args: Vec<String> = env::args().skip(1).collect();
+    let config = match config::load::<WebServerConfig>("web_server", &args) {
+        Ok((config, positional)) if positional.is_empty() => config,
+        Ok(_) => {
+            println!("Usage: cargo run --bin web_server [--config <path>] [--<key> <value> ...]");
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    log::set_level(config.log_level);
+    let protocol = config.encoding().and_then(|encoding| Ok((encoding, config.replication()?)));
+    let (encoding, replication) = match protocol {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Invalid web server configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    fs::create_dir_all(&config.data_dir)?;
+    let settings = web::Data::new(Settings {
+        proxy: config.proxy.clone(),
+        cache_file: Path::new(&config.data_dir).join("list.json"),
+        quorum: config.quorum,
+        encoding,
+        replication,
+    });
+
+    HttpServer::new(move || {
         App::new()
             .wrap(Cors::permissive())
+            .app_data(settings.clone())
             .service(get_list)
             .service(add_change)
             .service(generate_id)
     })
-    .bind("127.0.0.1:5000")?
+    .bind(&config.bind)?
     .run()
     .await
-}
\ No newline at end of file
+}
@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::failure_detector::FailureDetectorConfig;
+use crate::log::LogLevel;
+use crate::protocol::{Encoding, Replication};
+use crate::quorum::{QuorumConfig, RequestOptions};
+use crate::storage::StorageConfig;
+
+// the settings of a binary come from `config/<binary>.json` (or the file given with
+// --config <path>), then from SLDE_<BINARY>_<KEY> environment variables, then from
+// --<key> <value> arguments, each overriding the ones before. A key names a field, dots
+// (`__` in variables) a nested one: --quorum.w 2, SLDE_SERVER_QUORUM__W=2. Values are json,
+// or strings when they don't parse as json. Returns the arguments that aren't options
+pub fn load<T: DeserializeOwned>(binary: &str, args: &[String]) -> Result<(T, Vec<String>), String> {
+    let mut path = format!("config/{}.json", binary);
+    let mut overrides = Vec::new();
+    let mut positional = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.strip_prefix("--") {
+            Some(key) => {
+                let value = args.next().ok_or_else(|| format!("Missing value for --{}", key))?;
+                if key == "config" {
+                    path = value.clone();
+                } else {
+                    overrides.push((key.replace('-', "_"), value.clone()));
+                }
+            }
+            None => positional.push(arg.clone()),
+        }
+    }
+
+    let mut config = Value::Object(load_json_or_default::<Map<String, Value>>(&path)?);
+    let prefix = format!("SLDE_{}_", binary.to_uppercase());
+    let mut variables: Vec<(String, String)> = env::vars()
+        .filter_map(|(name, value)| {
+            let key = name.strip_prefix(&prefix)?.to_lowercase().replace("__", ".");
+            Some((key, value))
+        })
+        .collect();
+    variables.sort();
+    for (key, value) in variables.into_iter().chain(overrides) {
+        set(&mut config, &key, &value)?;
+    }
+    let config = serde_json::from_value(config).map_err(|e| format!("Invalid {} configuration: {}", binary, e))?;
+    Ok((config, positional))
+}
+
+// a json file, or the defaults when there is no file
+pub fn load_json_or_default<T: DeserializeOwned + Default>(path: &str) -> Result<T, String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(T::default()),
+        Err(e) => return Err(format!("Error reading {}: {}", path, e)),
+    };
+    serde_json::from_str(&contents).map_err(|e| format!("Error parsing {}: {}", path, e))
+}
+
+fn set(config: &mut Value, key: &str, raw: &str) -> Result<(), String> {
+    let value = serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()));
+    let mut node = config;
+    let mut segments = key.split('.').peekable();
+    while let Some(segment) = segments.next() {
+        if segment.is_empty() {
+            return Err(format!("Invalid option {}", key));
+        }
+        if !node.is_object() {
+            *node = Value::Object(Map::new());
+        }
+        let object = node.as_object_mut().unwrap();
+        if segments.peek().is_none() {
+            object.insert(segment.to_string(), value);
+            return Ok(());
+        }
+        node = object.entry(segment).or_insert_with(|| Value::Object(Map::new()));
+    }
+    Ok(())
+}
+
+// settings shared by the binaries that aren't in their own file are read from the files in
+// data/ that held them before
+fn or_load<T: Clone>(value: &Option<T>, load: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    match value {
+        Some(x) => Ok(x.clone()),
+        None => load(),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    // where the server keeps its lists, hints, op logs and write-ahead log; "{id}" is the server id
+    pub data_dir: String,
+    // the address other servers reach this one on; "{port}" is its port in the peer list
+    pub bind: String,
+    // the proxy's backend, which client requests come from
+    pub proxy: String,
+    // {"<server id>": "<port>"}, data/ports.json when missing
+    pub peers: Option<HashMap<String, String>>,
+    // data/quorum.json when missing
+    pub quorum: Option<QuorumConfig>,
+    // data/protocol.json when missing
+    pub encoding: Option<Encoding>,
+    // data/storage.json when missing; its paths default to files in the data directory
+    pub storage: Option<StorageConfig>,
+    pub failure_detector: DetectorSettings,
+    pub log_level: LogLevel,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            data_dir: "data/server_{id}".to_string(),
+            bind: "tcp://*:{port}".to_string(),
+            proxy: "tcp://localhost:5560".to_string(),
+            peers: None,
+            quorum: None,
+            encoding: None,
+            storage: None,
+            failure_detector: DetectorSettings::default(),
+            log_level: LogLevel::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn data_dir_for(&self, server_id: &str) -> PathBuf {
+        PathBuf::from(self.data_dir.replace("{id}", server_id))
+    }
+
+    pub fn bind_for(&self, port: &str) -> String {
+        self.bind.replace("{port}", port)
+    }
+
+    pub fn peers(&self) -> Result<HashMap<String, String>, String> {
+        let peers = or_load(&self.peers, || load_ports("data/ports.json"))?;
+        match peers.iter().find(|(_, port)| port.parse::<u16>().is_err()) {
+            Some((id, port)) => Err(format!("Invalid port {:?} for server {}", port, id)),
+            None => Ok(peers),
+        }
+    }
+
+    pub fn quorum(&self) -> Result<QuorumConfig, String> {
+        let quorum = or_load(&self.quorum, || QuorumConfig::load("data/quorum.json"))?;
+        quorum.validate()?;
+        Ok(quorum)
+    }
+
+    pub fn encoding(&self) -> Result<Encoding, String> {
+        or_load(&self.encoding, || Encoding::load("data/protocol.json"))
+    }
+
+    pub fn storage(&self) -> Result<StorageConfig, String> {
+        or_load(&self.storage, || StorageConfig::load("data/storage.json"))
+    }
+
+    pub fn failure_detector(&self, encoding: Encoding) -> Result<FailureDetectorConfig, String> {
+        let settings = &self.failure_detector;
+        if settings.gossip_interval_ms == 0 {
+            return Err("gossip_interval_ms must be positive".to_string());
+        }
+        if settings.suspect_after_ms >= settings.dead_after_ms {
+            return Err(format!(
+                "suspect_after_ms ({}) must be less than dead_after_ms ({})",
+                settings.suspect_after_ms, settings.dead_after_ms
+            ));
+        }
+        Ok(FailureDetectorConfig {
+            heartbeat_interval: Duration::from_millis(settings.gossip_interval_ms),
+            suspect_after: Duration::from_millis(settings.suspect_after_ms),
+            dead_after: Duration::from_millis(settings.dead_after_ms),
+            encoding,
+            ..FailureDetectorConfig::default()
+        })
+    }
+}
+
+// how often a server gossips its heartbeat, and how long a peer may stay silent before it
+// is suspect and then dead, in milliseconds
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DetectorSettings {
+    pub gossip_interval_ms: u64,
+    pub suspect_after_ms: u64,
+    pub dead_after_ms: u64,
+}
+
+impl Default for DetectorSettings {
+    fn default() -> Self {
+        let config = FailureDetectorConfig::default();
+        Self {
+            gossip_interval_ms: config.heartbeat_interval.as_millis() as u64,
+            suspect_after_ms: config.suspect_after.as_millis() as u64,
+            dead_after_ms: config.dead_after.as_millis() as u64,
+        }
+    }
+}
+
+// {"<server id>": "<port>", ...}
+fn load_ports(path: &str) -> Result<HashMap<String, String>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Error reading {}: {}", path, e))?;
+    serde_json::from_str(&contents).map_err(|e| format!("Error parsing {}: {}", path, e))
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebServerConfig {
+    // holds list.json, the list last read or changed through the web server
+    pub data_dir: String,
+    // the address the browser reaches the web server on
+    pub bind: String,
+    // the proxy's frontend, where requests to the servers go
+    pub proxy: String,
+    // the read and write quorums of requests that don't ask for their own, the cluster's when missing
+    pub quorum: RequestOptions,
+    // data/protocol.json when missing
+    pub encoding: Option<Encoding>,
+    pub replication: Option<Replication>,
+    pub log_level: LogLevel,
+}
+
+impl Default for WebServerConfig {
+    fn default() -> Self {
+        Self {
+            data_dir: "data/web_server".to_string(),
+            bind: "127.0.0.1:5000".to_string(),
+            proxy: "tcp://localhost:5559".to_string(),
+            quorum: RequestOptions::default(),
+            encoding: None,
+            replication: None,
+            log_level: LogLevel::default(),
+        }
+    }
+}
+
+impl WebServerConfig {
+    pub fn encoding(&self) -> Result<Encoding, String> {
+        or_load(&self.encoding, || Encoding::load("data/protocol.json"))
+    }
+
+    pub fn replication(&self) -> Result<Replication, String> {
+        or_load(&self.replication, || Replication::load("data/protocol.json"))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyConfig {
+    // where the web server sends requests
+    pub frontend: String,
+    // where the servers take them from
+    pub backend: String,
+    pub log_level: LogLevel,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            frontend: "tcp://*:5559".to_string(),
+            backend: "tcp://*:5560".to_string(),
+            log_level: LogLevel::default(),
+        }
+    }
+}
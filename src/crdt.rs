@@ -1,241 +1,920 @@
-use std::{collections::{HashMap, HashSet}, hash::{Hash, Hasher}};
-use serde_json::{json, Value};
-use serde::{Serialize, Deserialize};
-#[derive(Clone, Debug, Eq, Serialize, Deserialize)]
-pub struct Item {
-    item_name: String,
-    target: u64,
-    bought: u64,
-    replica: String,
-    timestamp: u64,
-    deleted: bool,
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde_json::Value;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CrdtError {
+    // a list read from json is missing a field or has one of the wrong type
+    Malformed { reason: String },
+    // a change to an item the list doesn't have, e.g. one removed by someone else
+    NotPresent { item_id: String },
 }
 
-impl Hash for Item {
-    fn hash<H: Hasher>(&self, state: &mut H){
-        self.item_name.hash(state);
+impl fmt::Display for CrdtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CrdtError::Malformed { reason } => write!(f, "malformed list: {}", reason),
+            CrdtError::NotPresent { item_id } => write!(f, "item {} is not in the list", item_id),
+        }
     }
 }
 
-impl PartialEq for Item {
-    fn eq(&self, other: &Item)->bool{
-        self.item_name == other.item_name
+impl std::error::Error for CrdtError {}
+
+fn malformed(reason: String) -> CrdtError {
+    CrdtError::Malformed { reason }
+}
+
+fn str_field(json: &Value, name: &str) -> Result<String, CrdtError> {
+    json[name]
+        .as_str()
+        .map(|value| value.to_string())
+        .ok_or_else(|| malformed(format!("\"{}\" is not a string", name)))
+}
+
+fn u64_field(json: &Value, name: &str) -> Result<u64, CrdtError> {
+    json[name]
+        .as_u64()
+        .ok_or_else(|| malformed(format!("\"{}\" is not a number", name)))
+}
+
+fn array_field<'a>(json: &'a Value, name: &str) -> Result<&'a Vec<Value>, CrdtError> {
+    json[name]
+        .as_array()
+        .ok_or_else(|| malformed(format!("\"{}\" is not an array", name)))
+}
+
+// a unique event: the n-th write made by a replica
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Dot {
+    pub replica: String,
+    pub counter: u64,
+}
+
+// how two replicas' histories relate: one saw everything the other did and more, both
+// saw the same, or each saw something the other didn't
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CausalOrder {
+    Before,
+    After,
+    Equal,
+    Concurrent,
+}
+
+// the highest counter seen from every replica, a missing replica counting as 0
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct VersionVector(BTreeMap<String, u64>);
+
+impl VersionVector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, replica: &str) -> u64 {
+        self.0.get(replica).copied().unwrap_or(0)
+    }
+
+    pub fn set(&mut self, replica: &str, counter: u64) {
+        self.0.insert(replica.to_string(), counter);
+    }
+
+    pub fn remove(&mut self, replica: &str) {
+        self.0.remove(replica);
+    }
+
+    pub fn contains(&self, dot: &Dot) -> bool {
+        self.get(&dot.replica) >= dot.counter
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, u64)> + '_ {
+        self.0.iter().map(|(replica, counter)| (replica, *counter))
+    }
+
+    // the highest counter of every replica on either side
+    pub fn merge(&mut self, other: &VersionVector) {
+        for (replica, counter) in other.iter() {
+            let entry = self.0.entry(replica.clone()).or_insert(0);
+            *entry = (*entry).max(counter);
+        }
+    }
+
+    pub fn compare(&self, other: &VersionVector) -> CausalOrder {
+        let ours = self.iter().any(|(replica, counter)| counter > other.get(replica));
+        let theirs = other.iter().any(|(replica, counter)| counter > self.get(replica));
+        order(ours, theirs)
     }
 }
 
-impl Item{
-    fn to_json(&self)->serde_json::Value{
-        let json = json!({
-            "item_name": self.item_name,
-            "target": self.target,
-            "bought": self.bought,
-            "replica": self.replica,
-            "timestamp": self.timestamp,
-            "deleted": self.deleted
+// `ours` has something `theirs` doesn't, `theirs` has something `ours` doesn't
+fn order(ours: bool, theirs: bool) -> CausalOrder {
+    match (ours, theirs) {
+        (false, false) => CausalOrder::Equal,
+        (true, false) => CausalOrder::After,
+        (false, true) => CausalOrder::Before,
+        (true, true) => CausalOrder::Concurrent,
+    }
+}
+
+// every dot a replica has seen: a version vector for the contiguous part plus the
+// dots that arrived out of order, folded into the vector once the gap is filled
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CausalContext {
+    vv: VersionVector,
+    cloud: BTreeSet<Dot>,
+}
+
+impl CausalContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contains(&self, dot: &Dot) -> bool {
+        self.vv.contains(dot) || self.cloud.contains(dot)
+    }
+
+    // a fresh dot for the replica, after everything it has written so far
+    pub fn next(&self, replica: &str) -> Dot {
+        let in_cloud = self
+            .cloud
+            .iter()
+            .filter(|dot| dot.replica == replica)
+            .map(|dot| dot.counter)
+            .max()
+            .unwrap_or(0);
+        let counter = self.vv.get(replica).max(in_cloud) + 1;
+        Dot { replica: replica.to_string(), counter }
+    }
+
+    pub fn insert(&mut self, dot: Dot) {
+        if !self.contains(&dot) {
+            self.cloud.insert(dot);
+            self.compact();
+        }
+    }
+
+    pub fn merge(&mut self, other: &CausalContext) {
+        self.vv.merge(&other.vv);
+        self.cloud.extend(other.cloud.iter().cloned());
+        self.compact();
+    }
+
+    // the highest counter of every replica below which nothing is missing
+    pub fn version_vector(&self) -> &VersionVector {
+        &self.vv
+    }
+
+    pub fn dots(&self) -> impl Iterator<Item = Dot> + '_ {
+        self.vv
+            .iter()
+            .flat_map(|(replica, counter)| (1..=counter).map(move |counter| Dot { replica: replica.clone(), counter }))
+            .chain(self.cloud.iter().cloned())
+    }
+
+    // compares the dots both sides have seen, out of order ones included
+    pub fn compare(&self, other: &CausalContext) -> CausalOrder {
+        order(!other.includes(self), !self.includes(other))
+    }
+
+    // every dot of the other context is in this one
+    fn includes(&self, other: &CausalContext) -> bool {
+        let contiguous = other.vv.iter().all(|(replica, counter)| {
+            (self.vv.get(replica) + 1..=counter).all(|counter| self.cloud.contains(&Dot { replica: replica.clone(), counter }))
         });
-        json
+        contiguous && other.cloud.iter().all(|dot| self.contains(dot))
+    }
+
+    fn compact(&mut self) {
+        // the cloud is ordered by replica then counter, so a gap that just closed
+        // is folded in one pass
+        let cloud = std::mem::take(&mut self.cloud);
+        for dot in cloud {
+            let counter = self.vv.get(&dot.replica);
+            if dot.counter == counter + 1 {
+                self.vv.set(&dot.replica, dot.counter);
+            } else if dot.counter > counter {
+                self.cloud.insert(dot);
+            }
+        }
     }
 }
 
-impl PartialEq for AWSet {
-    fn eq(&self, other: &Self) -> bool {
-        self.id == other.id && self.s == other.s
+// a replicated state that converges: merge is commutative, associative and idempotent.
+// The deltas returned by the mutators are states too, joined with the same merge.
+pub trait Crdt: Clone + Default {
+    fn merge(&mut self, other: &Self);
+}
+
+// a crdt that records the dots it has seen, so a delta can be checked to directly
+// follow the state before it is joined
+pub trait Causal: Crdt {
+    fn context(&self) -> &CausalContext;
+
+    // a delta can only be joined when every dot in it was already seen or directly follows
+    // what we have from its replica; after a gap the full state is needed, so the causal
+    // context stays compact
+    fn can_merge_delta(&self, delta: &Self) -> bool {
+        let mut context = self.context().clone();
+        let mut dots: Vec<Dot> = delta.context().dots().collect();
+        dots.sort();
+        for dot in dots {
+            if context.contains(&dot) {
+                continue;
+            }
+            if context.vv.get(&dot.replica) + 1 != dot.counter {
+                return false;
+            }
+            context.insert(dot);
+        }
+        true
+    }
+
+    // joins a delta produced by one of the mutators, returns false if it doesn't follow our context
+    fn merge_delta(&mut self, delta: &Self) -> bool {
+        if !self.can_merge_delta(delta) {
+            return false;
+        }
+        self.merge(delta);
+        true
+    }
+
+    // every write takes a dot, so a state whose context includes the other's already has
+    // everything the other has: merging it in changes nothing
+    fn compare(&self, other: &Self) -> CausalOrder {
+        self.context().compare(other.context())
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct AWSet {
-    #[serde(skip)]
-    pub id: String,
-    s: HashSet<Item>, // The set of items
-    c: HashMap<String, u64>, // Causal context, mapping replica to max timestamp
+// a counter that can go up and down, the increments and decrements of every replica
+// kept apart so concurrent changes add up instead of overwriting each other
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PNCounter {
+    p: BTreeMap<String, u64>,
+    n: BTreeMap<String, u64>,
 }
 
-impl AWSet {
+impl PNCounter {
     pub fn new() -> Self {
-        Self {
-            id: String::new(),
-            s: HashSet::new(),
-            c: HashMap::new(),
+        Self::default()
+    }
+
+    pub fn value(&self) -> i64 {
+        self.p.values().sum::<u64>() as i64 - self.n.values().sum::<u64>() as i64
+    }
+
+    pub fn increment(&mut self, replica: &str, amount: u64) {
+        *self.p.entry(replica.to_string()).or_insert(0) += amount;
+    }
+
+    pub fn decrement(&mut self, replica: &str, amount: u64) {
+        *self.n.entry(replica.to_string()).or_insert(0) += amount;
+    }
+
+    // an absolute value is written as the change from the value we see
+    pub fn set(&mut self, replica: &str, value: i64) {
+        let difference = value - self.value();
+        if difference > 0 {
+            self.increment(replica, difference as u64);
+        } else if difference < 0 {
+            self.decrement(replica, difference.unsigned_abs());
         }
     }
 
-    pub fn set_id(&mut self, id: String){
-        self.id = id;
+    // a plain number is a quantity written before the counters, all of it by the writer
+    fn from_v1(json: &Value, replica: &str) -> Result<PNCounter, CrdtError> {
+        let mut counter = PNCounter::new();
+        match json.as_i64() {
+            Some(value) => counter.set(replica, value),
+            None => {
+                let counts = |name: &str| {
+                    serde_json::from_value(json[name].clone())
+                        .map_err(|e| malformed(format!("counter \"{}\": {}", name, e)))
+                };
+                counter.p = counts("p")?;
+                counter.n = counts("n")?;
+            }
+        }
+        Ok(counter)
+    }
+}
+
+impl Crdt for PNCounter {
+    fn merge(&mut self, other: &PNCounter) {
+        for (replica, count) in &other.p {
+            let entry = self.p.entry(replica.clone()).or_insert(0);
+            *entry = (*entry).max(*count);
+        }
+        for (replica, count) in &other.n {
+            let entry = self.n.entry(replica.clone()).or_insert(0);
+            *entry = (*entry).max(*count);
+        }
     }
-          
-    pub fn from_json(&mut self, json: serde_json::Value ){
-        let (first_key, first_value) = if let Some(obj) = json.as_object() {
-            obj.iter().next().map(|(key, value)| (key, value))
+}
+
+// a hybrid logical clock reading: the wall time in milliseconds, a logical counter for
+// writes that would otherwise not move the clock forward, and the replica that made it.
+// Readings are ordered by all three, so two replicas never tie, and a reading taken after
+// seeing another is later than it even when the wall clock of the writer is behind
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Hlc {
+    pub wall: u64,
+    pub logical: u32,
+    pub replica: String,
+}
+
+impl Hlc {
+    // a reading later than this one, the latest the replica has seen; `now` is the
+    // wall time of the replica, see wall_clock
+    pub fn tick(&self, replica: &str, now: u64) -> Hlc {
+        if now > self.wall {
+            Hlc { wall: now, logical: 0, replica: replica.to_string() }
         } else {
-            None
-        }.expect("JSON object is empty or invalid");
-        self.id = first_key.to_owned();
-        let s_array = first_value["s"].as_array().unwrap();
-        for item in s_array{
-            let new_item = Item{
-                item_name: item["item_name"].as_str().unwrap().to_string(),
-                target: item["target"].as_u64().unwrap(),
-                bought: item["bought"].as_u64().unwrap(),
-                replica: item["replica"].as_str().unwrap().to_string(),
-                timestamp: item["timestamp"].as_u64().unwrap(),
-                deleted: item["deleted"].as_bool().unwrap()
-            };
-            self.s.insert(new_item);
+            Hlc { wall: self.wall, logical: self.logical + 1, replica: replica.to_string() }
+        }
+    }
+}
+
+// the latest of the readings
+impl Crdt for Hlc {
+    fn merge(&mut self, other: &Hlc) {
+        if *other > *self {
+            *self = other.clone();
         }
-        let c_array = first_value["c"].as_array().unwrap();
-        for context in c_array{
-            self.c.insert(context["replica"].as_str().unwrap().to_string(), context["timestamp"].as_u64().unwrap());
+    }
+}
+
+// milliseconds since the unix epoch, the physical part of new Hlc readings
+pub fn wall_clock() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// a value where the latest write wins, by the hybrid logical clock reading of the write
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LWWRegister<T> {
+    value: T,
+    timestamp: Hlc,
+}
+
+impl<T: Clone + Default> LWWRegister<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+
+    pub fn timestamp(&self) -> &Hlc {
+        &self.timestamp
+    }
+
+    // a write by the replica, after the one we have
+    pub fn set(&mut self, value: T, replica: &str) {
+        let timestamp = self.timestamp.tick(replica, wall_clock());
+        self.set_at(value, timestamp);
+    }
+
+    // the write is kept only if it is later than the one we have
+    pub fn set_at(&mut self, value: T, timestamp: Hlc) {
+        self.merge(&LWWRegister { value, timestamp });
+    }
+}
+
+impl<T: Clone + Default> Crdt for LWWRegister<T> {
+    fn merge(&mut self, other: &LWWRegister<T>) {
+        if other.timestamp > self.timestamp {
+            *self = other.clone();
         }
+    }
+}
+
+// a value that keeps every write made concurrently, until a write that saw them all
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MVRegister<T> {
+    values: Vec<(Dot, T)>,
+    #[serde(rename = "context")]
+    c: CausalContext,
+}
+
+impl<T> Default for MVRegister<T> {
+    fn default() -> Self {
+        Self { values: Vec::new(), c: CausalContext::new() }
+    }
+}
+
+impl<T: Clone> MVRegister<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn values(&self) -> Vec<&T> {
+        self.values.iter().map(|(_, value)| value).collect()
+    }
+
+    // the values with the dot that wrote them, telling which replica wrote each
+    pub fn siblings(&self) -> &[(Dot, T)] {
+        &self.values
+    }
+
+    pub fn set(&mut self, value: T, replica: &str) {
+        let dot = self.c.next(replica);
+        self.c.insert(dot.clone());
+        self.values = vec![(dot, value)];
+    }
+}
+
+impl<T: Clone> Crdt for MVRegister<T> {
+    fn merge(&mut self, other: &MVRegister<T>) {
+        self.values = join_dotted(&self.values, &self.c, &other.values, &other.c, |(dot, _)| dot);
+        self.c.merge(&other.c);
+    }
+}
+
+impl<T: Clone> Causal for MVRegister<T> {
+    fn context(&self) -> &CausalContext {
+        &self.c
+    }
+}
+
+// a write to a map entry, made at a dot
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Version<V> {
+    pub dot: Dot,
+    pub value: V,
+}
+
+// observed-remove map whose values are crdts. An entry is present while it has at least
+// one version; writing or removing an entry drops the versions the writer had seen, so a
+// concurrent write the remover never saw keeps the entry alive. The value of an entry is
+// the merge of its versions, every write carrying the value it saw plus its own change.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ORMap<K: Ord, V> {
+    // versions ordered by dot
+    entries: BTreeMap<K, Vec<Version<V>>>,
+    #[serde(rename = "context")]
+    c: CausalContext,
+}
+
+impl<K: Ord, V> Default for ORMap<K, V> {
+    fn default() -> Self {
+        Self { entries: BTreeMap::new(), c: CausalContext::new() }
+    }
+}
+
+impl<K: Ord + Clone, V: Crdt> ORMap<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.entries.get(key).map(|versions| Self::joined(versions))
+    }
+
+    pub fn versions(&self, key: &K) -> &[Version<V>] {
+        self.entries.get(key).map(|versions| versions.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, V)> + '_ {
+        self.entries.iter().map(|(key, versions)| (key, Self::joined(versions)))
+    }
 
+    pub fn len(&self) -> usize {
+        self.entries.len()
     }
-    
 
-    pub fn to_json(&self)->Value{
-        let mut s_array: Vec<Value> = Vec::new();
-        for item in &self.s{
-            s_array.push(item.to_json());
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // the mutators return their delta: the version they wrote plus the dots it
+    // superseded, to be replicated in place of the whole map
+    pub fn update(&mut self, key: K, replica: &str, change: impl FnOnce(&mut V)) -> ORMap<K, V> {
+        let dot = self.c.next(replica);
+        let mut value = self.get(&key).unwrap_or_default();
+        change(&mut value);
+        let version = Version { dot: dot.clone(), value };
+        let mut delta = self.drop_versions(&key);
+        self.c.insert(dot.clone());
+        self.entries.insert(key.clone(), vec![version.clone()]);
+        delta.c.insert(dot);
+        delta.entries.insert(key, vec![version]);
+        delta
+    }
+
+    // drops every version we have seen; versions written concurrently elsewhere survive.
+    // The remove takes a dot of its own, so the contexts of two maps tell them apart
+    pub fn remove(&mut self, key: &K, replica: &str) -> ORMap<K, V> {
+        let dot = self.c.next(replica);
+        let mut delta = self.drop_versions(key);
+        self.c.insert(dot.clone());
+        delta.c.insert(dot);
+        delta
+    }
+
+    // the delta of the versions of the key being superseded
+    fn drop_versions(&mut self, key: &K) -> ORMap<K, V> {
+        let mut delta = ORMap::new();
+        for version in self.entries.remove(key).unwrap_or_default() {
+            delta.c.insert(version.dot);
         }
-        let mut c_array: Vec<Value> = Vec::new();
-        for item in &self.c{
-            let context = json!({"replica":item.0,"timestamp":item.1});
-            c_array.push(context)
+        delta
+    }
+
+    // adds a version read from storage
+    fn insert_version(&mut self, key: K, version: Version<V>) {
+        self.c.insert(version.dot.clone());
+        let versions = self.entries.entry(key).or_default();
+        if !versions.iter().any(|other| other.dot == version.dot) {
+            versions.push(version);
+            versions.sort_by(|a, b| a.dot.cmp(&b.dot));
         }
-        let final_json = json!({&self.id:{"s":s_array,"c":c_array}});
-        final_json
     }
 
-    pub fn add(&mut self, item_name: &str, target: u64, bought: u64, replica: &str, deleted: bool) {
-        let next_timestamp = self.c.get(replica).cloned().unwrap_or(0) + 1;
-        let item = Item {
-            item_name: item_name.to_string(),
-            target: target,
-            bought: bought,
-            replica: replica.to_string(),
-            timestamp: next_timestamp,
-            deleted: deleted
-        };
-        self.s.insert(item);
-        self.c.insert(replica.to_string(), next_timestamp);
+    fn joined(versions: &[Version<V>]) -> V {
+        let mut joined = V::default();
+        for version in versions {
+            joined.merge(&version.value);
+        }
+        joined
     }
+}
 
-    pub fn remove(&mut self, item_name: &str, replica: &str) {
-        let current_timestamp = self.c.get(replica).copied().unwrap_or(0);
+impl<K: Ord + Clone, V: Crdt> Crdt for ORMap<K, V> {
+    fn merge(&mut self, other: &ORMap<K, V>) {
+        let keys: BTreeSet<K> = self.entries.keys().chain(other.entries.keys()).cloned().collect();
+        let empty = Vec::new();
+        for key in keys {
+            let ours = self.entries.get(&key).unwrap_or(&empty);
+            let theirs = other.entries.get(&key).unwrap_or(&empty);
+            let merged = join_dotted(ours, &self.c, theirs, &other.c, |version| &version.dot);
+            if merged.is_empty() {
+                self.entries.remove(&key);
+            } else {
+                self.entries.insert(key, merged);
+            }
+        }
+        self.c.merge(&other.c);
+    }
+}
 
-        let item_to_remove = Item {
-            item_name: item_name.to_string(),
-            target: 0,
-            bought: 0,
-            replica: "".to_string(),
-            timestamp: 0,
-            deleted: false,
-        };
+impl<K: Ord + Clone, V: Crdt> Causal for ORMap<K, V> {
+    fn context(&self) -> &CausalContext {
+        &self.c
+    }
+}
 
-        let removed_item = self.s.take(&item_to_remove).unwrap();
+// joins two sets of dotted writes: a write survives if both sides have it, or if the side
+// without it never saw its dot
+fn join_dotted<T: Clone>(
+    ours: &[T],
+    our_context: &CausalContext,
+    theirs: &[T],
+    their_context: &CausalContext,
+    dot: impl Fn(&T) -> &Dot,
+) -> Vec<T> {
+    let has = |writes: &[T], d: &Dot| writes.iter().any(|write| dot(write) == d);
+    let mut merged: Vec<T> = ours
+        .iter()
+        .filter(|write| has(theirs, dot(write)) || !their_context.contains(dot(write)))
+        .cloned()
+        .collect();
+    for write in theirs {
+        if !has(&merged, dot(write)) && !our_context.contains(dot(write)) {
+            merged.push(write.clone());
+        }
+    }
+    merged.sort_by(|a, b| dot(a).cmp(dot(b)));
+    merged
+}
 
-        let new_item = Item{
-            item_name: item_name.to_string(),
-            target: removed_item.target,
-            bought: removed_item.bought,
-            replica: replica.to_string(),
-            timestamp: current_timestamp +1,
-            deleted: true
-        };
+// the quantities of an item that can be incremented and decremented
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Quantity {
+    Target,
+    Bought,
+}
 
-        self.s.insert(new_item);
+// a shopping item, the value of an entry of the list. The list keys it by an id given
+// when it is added, so its name can change like its amounts
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Item {
+    pub name: MVRegister<String>,
+    pub target: PNCounter,
+    pub bought: PNCounter,
+    // when and by whom the item was last written, unknown (wall 0) for items from
+    // before version 4 of the list
+    #[serde(default)]
+    pub modified: Hlc,
+}
 
-        self.c.insert(replica.to_string(), current_timestamp + 1);
+impl Item {
+    pub fn quantity(&self, quantity: Quantity) -> &PNCounter {
+        match quantity {
+            Quantity::Target => &self.target,
+            Quantity::Bought => &self.bought,
+        }
     }
-    
 
-    pub fn update_item_amounts(&mut self, item_name: &str, new_target: u64, new_bought: u64, replica: &str) {
-        // Get the current timestamp for the given replica, or default to 0
-        let current_timestamp = self.c.get(replica).copied().unwrap_or(0);
+    fn quantity_mut(&mut self, quantity: Quantity) -> &mut PNCounter {
+        match quantity {
+            Quantity::Target => &mut self.target,
+            Quantity::Bought => &mut self.bought,
+        }
+    }
 
-        let item_to_remove = Item {
-            item_name: item_name.to_string(),
-            target: 0,
-            bought: 0,
-            replica: "".to_string(),
-            timestamp: 0,
-            deleted: false,
-        };
+    // items from before ids are keyed by their name, written at the same dot by every
+    // replica that migrates them
+    fn migrated(name: &str, target: PNCounter, bought: PNCounter) -> Item {
+        let dot = Dot { replica: "migrated".to_string(), counter: 1 };
+        let mut register = MVRegister::new();
+        register.c.insert(dot.clone());
+        register.values.push((dot, name.to_string()));
+        Item { name: register, target, bought, modified: Hlc::default() }
+    }
+}
 
-        let removed_item = self.s.take(&item_to_remove).unwrap();
+impl Crdt for Item {
+    fn merge(&mut self, other: &Item) {
+        self.name.merge(&other.name);
+        self.target.merge(&other.target);
+        self.bought.merge(&other.bought);
+        self.modified.merge(&other.modified);
+    }
+}
 
-        let new_item = Item{
-            item_name: item_name.to_string(),
-            target: new_target,
-            bought: new_bought,
-            replica: replica.to_string(),
-            timestamp: current_timestamp +1,
-            deleted: removed_item.deleted
-        };
+// the version of the serialized form of a list, written in its "version" field and
+// checked when a list is read back. AWSet::migrate reads the older ones: version 1 is
+// the format from before the field, {id: {"s": [...], "c": [...]}}, version 2 keyed
+// items by name, version 3 had no modification time
+pub const SCHEMA_VERSION: u32 = 4;
 
-        self.s.insert(new_item);
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Schema;
 
-        self.c.insert(replica.to_string(), current_timestamp + 1);
+impl Serialize for Schema {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(SCHEMA_VERSION)
     }
+}
 
-    pub fn contains(&self, item_name: &str) -> bool {
-        self.s.iter().any(|item| item.item_name == item_name)
+impl<'de> Deserialize<'de> for Schema {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Schema, D::Error> {
+        match u32::deserialize(deserializer)? {
+            SCHEMA_VERSION => Ok(Schema),
+            version => Err(de::Error::custom(format!(
+                "list schema version {} is not supported, expected {}",
+                version, SCHEMA_VERSION
+            ))),
+        }
     }
+}
 
+// the items of a version 2 list
+#[derive(Clone, Default, Deserialize)]
+struct AmountsV2 {
+    target: PNCounter,
+    bought: PNCounter,
+}
+
+impl Crdt for AmountsV2 {
+    fn merge(&mut self, other: &AmountsV2) {
+        self.target.merge(&other.target);
+        self.bought.merge(&other.bought);
+    }
+}
+
+#[derive(Deserialize)]
+struct ListV2 {
+    id: String,
+    items: ORMap<String, AmountsV2>,
+}
+
+// the shopping list: items by id in an observed-remove map, so a concurrent write wins
+// over a remove and the amounts of concurrent writes add up. The versions of an item
+// written concurrently are its siblings, until a write that saw them all
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AWSet {
+    #[serde(rename = "version")]
+    schema: Schema,
+    pub id: String,
+    items: ORMap<String, Item>,
+}
+
+impl AWSet {
+    pub fn new() -> Self {
+        Self {
+            schema: Schema,
+            id: String::new(),
+            items: ORMap::new(),
+        }
+    }
 
-    pub fn merge(&mut self, other: &AWSet) {
-        let mut new_s = HashSet::new();
-        println!("causal context in c:{}", other.c.len());
+    pub fn set_id(&mut self, id: String){
+        self.id = id;
+    }
 
-        // keep items from the current set not known by the other causal context
-        for item in &self.s {
-            if !other.c.contains_key(&item.replica)|| other.c[&item.replica] < item.timestamp {
-                new_s.insert(item.clone());
-                println!("added an item from self");
+    // reads a list stored with any schema version
+    pub fn migrate(json: Value) -> Result<AWSet, CrdtError> {
+        match json.get("version").map(|version| version.as_u64()) {
+            Some(Some(2)) => {
+                let list: ListV2 = serde_json::from_value(json).map_err(|e| malformed(e.to_string()))?;
+                Ok(Self::from_v2(list))
+            }
+            Some(Some(3)) => {
+                let mut json = json;
+                json["version"] = Value::from(SCHEMA_VERSION);
+                serde_json::from_value(json).map_err(|e| malformed(e.to_string()))
+            }
+            Some(_) => serde_json::from_value(json).map_err(|e| malformed(e.to_string())),
+            None => {
+                let (id, body) = json
+                    .as_object()
+                    .and_then(|obj| obj.iter().next())
+                    .ok_or_else(|| malformed("expected a version or an object with the list under its id".to_string()))?;
+                Self::from_v1(id, body)
             }
-        
         }
+    }
 
-        // keep items from the other set not known by the current causal context
-        for item in &other.s {
-            if !self.c.contains_key(&item.replica) || self.c[&item.replica] < item.timestamp {
-                new_s.insert(item.clone());
-                println!("added an item from other");
+    fn from_v2(v2: ListV2) -> AWSet {
+        let mut list = AWSet::new();
+        list.id = v2.id;
+        list.items.c = v2.items.c;
+        for (name, versions) in v2.items.entries {
+            for version in versions {
+                let value = Item::migrated(&name, version.value.target, version.value.bought);
+                list.items.insert_version(name.clone(), Version { dot: version.dot, value });
             }
         }
+        list
+    }
 
-        // common items differing in relpica or timestamp
-        for item in &self.s {
-            if let Some(other_item) = other.s.iter().find(|&i| i.item_name == item.item_name) {
-                // if the items recplica is in the others context but not in self, save from other
-                if !self.c.contains_key(&other_item.replica) {
-                    new_s.insert(other_item.clone());
-                    println!("kept item from other (replica not in self's causal context)");
-                } else { // if its in both
-                    // chose the more recent one
-                    if item.timestamp > other_item.timestamp {
-                        new_s.insert(item.clone()); // Keep the item from self
-                        println!("kept item from self (timestamp: {})", item.timestamp);
-                    } else {
-                        new_s.insert(other_item.clone()); // Keep the item from other
-                        println!("kept item from other (timestamp: {})", other_item.timestamp);
-                    }
-                }
+    // version 1 lists from before dots hold one entry per element, with removed elements
+    // kept as "deleted" tombstones, later ones the counters of every version of an element
+    fn from_v1(id: &str, body: &Value) -> Result<AWSet, CrdtError> {
+        let mut list = AWSet::new();
+        list.id = id.to_owned();
+        for context in array_field(body, "c")? {
+            list.items.c.vv.set(&str_field(context, "replica")?, u64_field(context, "timestamp")?);
+        }
+        if let Some(cloud) = body["cloud"].as_array() {
+            for dot in cloud {
+                list.items.c.insert(Dot {
+                    replica: str_field(dot, "replica")?,
+                    counter: u64_field(dot, "timestamp")?,
+                });
+            }
+        }
+        for item in array_field(body, "s")? {
+            if item["deleted"].as_bool().unwrap_or(false) {
+                continue;
+            }
+            let item_name = str_field(item, "item_name")?;
+            let versions = match item["versions"].as_array() {
+                Some(versions) => versions.clone(),
+                None => vec![item.clone()],
+            };
+            for version in versions {
+                let replica = str_field(&version, "replica")?;
+                let value = Item::migrated(
+                    &item_name,
+                    PNCounter::from_v1(&version["target"], &replica)?,
+                    PNCounter::from_v1(&version["bought"], &replica)?,
+                );
+                let dot = Dot { replica, counter: u64_field(&version, "timestamp")? };
+                list.items.insert_version(item_name.clone(), Version { dot, value });
             }
         }
+        Ok(list)
+    }
+
+    // the removed elements version 1 lists, {id: body} for each list, still keep as
+    // "deleted" entries. They are dropped by the migration: the causal context of the
+    // list holds their dots, which is all a merge needs to keep the element removed
+    pub fn tombstones(json: &Value) -> usize {
+        json.as_object()
+            .into_iter()
+            .flat_map(|lists| lists.values())
+            .filter_map(|list| list["s"].as_array())
+            .flatten()
+            .filter(|item| item["deleted"].as_bool().unwrap_or(false))
+            .count()
+    }
 
-        // Update causal context by taking the max timestamps
-        for (replica, timestamp) in &other.c {
-            self.c
-                .entry(replica.clone())
-                .and_modify(|t| *t = (*t).max(*timestamp))
-                .or_insert(*timestamp);
+    // the mutators return the delta of the list, see ORMap::update. Setting an amount
+    // records the change from the amount the writer saw. A new item's id is the dot of
+    // the write that adds it
+    pub fn add(&mut self, name: &str, target: u64, bought: u64, replica: &str) -> (String, AWSet) {
+        let dot = self.items.c.next(replica);
+        let item_id = format!("{}:{}", dot.replica, dot.counter);
+        let delta = self.write(&item_id, replica, |item| {
+            item.name.set(name.to_string(), replica);
+            item.target.set(replica, target as i64);
+            item.bought.set(replica, bought as i64);
+        });
+        (item_id, delta)
+    }
+
+    pub fn remove(&mut self, item_id: &str, replica: &str) -> Result<AWSet, CrdtError> {
+        self.present(item_id)?;
+        let items = self.items.remove(&item_id.to_string(), replica);
+        Ok(AWSet { schema: Schema, id: self.id.clone(), items })
+    }
+
+    pub fn rename(&mut self, item_id: &str, name: &str, replica: &str) -> Result<AWSet, CrdtError> {
+        self.present(item_id)?;
+        Ok(self.write(item_id, replica, |item| item.name.set(name.to_string(), replica)))
+    }
+
+    // also resolves concurrent edits of the amounts, the write supersedes every sibling
+    pub fn update_item_amounts(&mut self, item_id: &str, new_target: u64, new_bought: u64, replica: &str) -> Result<AWSet, CrdtError> {
+        self.present(item_id)?;
+        Ok(self.write(item_id, replica, |item| {
+            item.target.set(replica, new_target as i64);
+            item.bought.set(replica, new_bought as i64);
+        }))
+    }
+
+    pub fn increment(&mut self, item_id: &str, quantity: Quantity, amount: u64, replica: &str) -> Result<AWSet, CrdtError> {
+        self.present(item_id)?;
+        Ok(self.write(item_id, replica, |item| item.quantity_mut(quantity).increment(replica, amount)))
+    }
+
+    pub fn decrement(&mut self, item_id: &str, quantity: Quantity, amount: u64, replica: &str) -> Result<AWSet, CrdtError> {
+        self.present(item_id)?;
+        Ok(self.write(item_id, replica, |item| item.quantity_mut(quantity).decrement(replica, amount)))
+    }
+
+    pub fn contains(&self, item_id: &str) -> bool {
+        self.items.contains_key(&item_id.to_string())
+    }
+
+    // the item with its siblings merged: every name they were given and the amounts of all
+    pub fn get(&self, item_id: &str) -> Option<Item> {
+        self.items.get(&item_id.to_string())
+    }
+
+    // the concurrent writes of an item, each with the replica that made it and the item as
+    // that replica left it; a single one when there is no conflict
+    pub fn siblings(&self, item_id: &str) -> &[Version<Item>] {
+        self.items.versions(&item_id.to_string())
+    }
+
+    // the ids of the items that have the name, among others if it has siblings
+    pub fn find(&self, name: &str) -> Vec<&String> {
+        self.items
+            .iter()
+            .filter(|(_, item)| item.name.values().contains(&&name.to_string()))
+            .map(|(item_id, _)| item_id)
+            .collect()
+    }
+
+    pub fn elements(&self) -> Vec<(&String, Item)> {
+        self.items.iter().collect()
+    }
+
+    // only add can write an item the list doesn't have
+    fn present(&self, item_id: &str) -> Result<(), CrdtError> {
+        if self.contains(item_id) {
+            Ok(())
+        } else {
+            Err(CrdtError::NotPresent { item_id: item_id.to_string() })
         }
+    }
+
+    // every write stamps the item, later than any of the versions it supersedes
+    fn write(&mut self, item_id: &str, replica: &str, change: impl FnOnce(&mut Item)) -> AWSet {
+        let items = self.items.update(item_id.to_string(), replica, |item| {
+            change(item);
+            item.modified = item.modified.tick(replica, wall_clock());
+        });
+        AWSet { schema: Schema, id: self.id.clone(), items }
+    }
+}
 
-        // Update the set
-        self.s = new_s;
+impl Crdt for AWSet {
+    fn merge(&mut self, other: &AWSet) {
+        self.items.merge(&other.items);
     }
+}
 
-    pub fn elements(&self) -> Vec<&Item> {
-        self.s.iter().collect()
+impl Causal for AWSet {
+    fn context(&self) -> &CausalContext {
+        &self.items.c
     }
 }
+
+// deserializes a list stored with any schema version, for values kept as json
+pub fn deserialize_migrating<'de, D: Deserializer<'de>>(deserializer: D) -> Result<AWSet, D::Error> {
+    AWSet::migrate(Value::deserialize(deserializer)?).map_err(de::Error::custom)
+}
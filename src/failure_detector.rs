@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::protocol::{Encoding, Request, Response};
+use crate::transport;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PeerState {
+    Alive,
+    Suspect,
+    Dead,
+}
+
+#[derive(Clone, Debug)]
+pub struct FailureDetectorConfig {
+    pub heartbeat_interval: Duration,
+    // a peer whose heartbeat has not advanced for this long is suspect
+    pub suspect_after: Duration,
+    // and after this long it is considered dead
+    pub dead_after: Duration,
+    // how many peers we gossip with on every round
+    pub fanout: usize,
+    // how long a gossip round waits for a peer to answer
+    pub gossip_timeout: Duration,
+    pub encoding: Encoding,
+}
+
+impl Default for FailureDetectorConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval: Duration::from_millis(500),
+            suspect_after: Duration::from_secs(3),
+            dead_after: Duration::from_secs(10),
+            fanout: 2,
+            gossip_timeout: Duration::from_millis(500),
+            encoding: Encoding::default(),
+        }
+    }
+}
+
+// the incarnation changes every time a server starts, so a restarted server with
+// a fresh counter still beats the entries the others remember about it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Heartbeat {
+    pub incarnation: u64,
+    pub counter: u64,
+}
+
+#[derive(Clone, Debug)]
+struct PeerInfo {
+    port: String,
+    heartbeat: Heartbeat,
+    last_seen: Instant,
+    // set when a request to the peer failed, cleared by its next heartbeat
+    failed: bool,
+}
+
+#[derive(Debug)]
+pub struct FailureDetector {
+    config: FailureDetectorConfig,
+    server_id: String,
+    heartbeat: Heartbeat,
+    peers: HashMap<String, PeerInfo>,
+    cursor: usize,
+}
+
+impl FailureDetector {
+    pub fn new(server_id: &str, config: FailureDetectorConfig) -> Self {
+        let incarnation = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        Self {
+            config,
+            server_id: server_id.to_string(),
+            heartbeat: Heartbeat { incarnation, counter: 0 },
+            peers: HashMap::new(),
+            cursor: 0,
+        }
+    }
+
+    pub fn config(&self) -> &FailureDetectorConfig {
+        &self.config
+    }
+
+    // new peers start alive, they get a full suspect_after window to send a heartbeat
+    pub fn add_peer(&mut self, server_id: &str, port: &str) {
+        if server_id == self.server_id {
+            return;
+        }
+        self.peers
+            .entry(server_id.to_string())
+            .and_modify(|peer| peer.port = port.to_string())
+            .or_insert(PeerInfo {
+                port: port.to_string(),
+                heartbeat: Heartbeat::default(),
+                last_seen: Instant::now(),
+                failed: false,
+            });
+    }
+
+    pub fn remove_peer(&mut self, server_id: &str) {
+        self.peers.remove(server_id);
+    }
+
+    // adds the cluster members the table doesn't have yet and updates their ports; the
+    // servers that left are taken out with remove_peer
+    pub fn sync_peers(&mut self, members: &HashMap<String, String>) {
+        for (id, port) in members {
+            self.add_peer(id, port);
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.heartbeat.counter += 1;
+    }
+
+    // our own heartbeat plus everything we know about the others
+    pub fn digest(&self) -> HashMap<String, Heartbeat> {
+        let mut digest: HashMap<String, Heartbeat> = self
+            .peers
+            .iter()
+            .map(|(id, peer)| (id.clone(), peer.heartbeat))
+            .collect();
+        digest.insert(self.server_id.clone(), self.heartbeat);
+        digest
+    }
+
+    // a peer is only refreshed when its heartbeat moved forward
+    pub fn merge(&mut self, digest: &HashMap<String, Heartbeat>) {
+        let now = Instant::now();
+        for (id, heartbeat) in digest {
+            if let Some(peer) = self.peers.get_mut(id) {
+                if *heartbeat > peer.heartbeat {
+                    peer.heartbeat = *heartbeat;
+                    peer.last_seen = now;
+                    peer.failed = false;
+                }
+            }
+        }
+    }
+
+    // called when a request to the peer failed or timed out
+    pub fn report_failure(&mut self, server_id: &str) {
+        if let Some(peer) = self.peers.get_mut(server_id) {
+            peer.failed = true;
+        }
+    }
+
+    pub fn state(&self, server_id: &str) -> PeerState {
+        if server_id == self.server_id {
+            return PeerState::Alive;
+        }
+        let peer = match self.peers.get(server_id) {
+            Some(x) => x,
+            None => return PeerState::Dead,
+        };
+        let elapsed = peer.last_seen.elapsed();
+        if elapsed >= self.config.dead_after {
+            PeerState::Dead
+        } else if elapsed >= self.config.suspect_after || peer.failed {
+            PeerState::Suspect
+        } else {
+            PeerState::Alive
+        }
+    }
+
+    pub fn port(&self, server_id: &str) -> Option<String> {
+        self.peers.get(server_id).map(|peer| peer.port.clone())
+    }
+
+    pub fn is_alive(&self, server_id: &str) -> bool {
+        self.state(server_id) == PeerState::Alive
+    }
+
+    pub fn states(&self) -> HashMap<String, PeerState> {
+        self.peers.keys().map(|id| (id.clone(), self.state(id))).collect()
+    }
+
+    // the next `fanout` peers in a round robin over the table, dead peers included
+    // so we notice when they come back
+    fn gossip_targets(&mut self) -> Vec<(String, String)> {
+        let mut ids: Vec<&String> = self.peers.keys().collect();
+        ids.sort();
+        let count = self.config.fanout.min(ids.len());
+        let mut targets = Vec::with_capacity(count);
+        for i in 0..count {
+            let id = ids[(self.cursor + i) % ids.len()];
+            targets.push((id.clone(), self.peers[id].port.clone()));
+        }
+        self.cursor = self.cursor.wrapping_add(count);
+        targets
+    }
+}
+
+// runs the heartbeat/gossip rounds in the background of a server
+pub fn spawn_gossip(detector: Arc<Mutex<FailureDetector>>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let context = zmq::Context::new();
+        loop {
+            let (message, targets, interval, timeout, encoding) = {
+                let mut detector = detector.lock().unwrap();
+                detector.tick();
+                (
+                    Request::Gossip { digest: detector.digest() },
+                    detector.gossip_targets(),
+                    detector.config.heartbeat_interval,
+                    detector.config.gossip_timeout,
+                    detector.config.encoding,
+                )
+            };
+            for (peer, port) in targets {
+                // push-pull: the peer answers with its own digest
+                match transport::call(&context, &port, &message, encoding, timeout) {
+                    Response::Gossip { digest } => detector.lock().unwrap().merge(&digest),
+                    _ => detector.lock().unwrap().report_failure(&peer),
+                }
+            }
+            thread::sleep(interval);
+        }
+    })
+}
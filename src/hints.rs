@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::crdt::{self, AWSet, Crdt};
+use crate::storage;
+
+#[derive(Clone, Debug)]
+pub struct HintQueueConfig {
+    // when a target has more pending hints than this, the oldest ones are dropped
+    pub max_hints_per_target: usize,
+    // hints that could not be delivered for this long are dropped
+    pub max_age: Duration,
+}
+
+impl Default for HintQueueConfig {
+    fn default() -> Self {
+        Self {
+            max_hints_per_target: 1000,
+            max_age: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+// a list this server holds on behalf of a replica that was down when it was written
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Hint {
+    pub target: String,
+    pub list_id: String,
+    // hint files written before the list schema version hold version 1 lists
+    #[serde(deserialize_with = "crdt::deserialize_migrating")]
+    pub awset: AWSet,
+    pub created_at: u64,
+    // bumped every time a newer write is folded into the hint, so a delivery
+    // that raced with a write doesn't delete the newer data
+    pub version: u64,
+}
+
+// hints are kept in memory and mirrored to one file per target server
+#[derive(Debug)]
+pub struct HintQueue {
+    dir: PathBuf,
+    config: HintQueueConfig,
+    hints: HashMap<String, Vec<Hint>>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl HintQueue {
+    pub fn open(dir: impl Into<PathBuf>, config: HintQueueConfig) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let mut hints = HashMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let contents = fs::read_to_string(&path)?;
+            match serde_json::from_str::<Vec<Hint>>(&contents) {
+                Ok(target_hints) if !target_hints.is_empty() => {
+                    hints.insert(target_hints[0].target.clone(), target_hints);
+                }
+                Ok(_) => {}
+                Err(e) => crate::warn!("Ignoring unreadable hint file {}: {}", path.display(), e),
+            }
+        }
+        Ok(Self { dir, config, hints })
+    }
+
+    // stores the list for the target, merged into any hint already pending for it
+    pub fn add(&mut self, target: &str, awset: &AWSet) -> io::Result<()> {
+        self.path_for(target)?;
+        let target_hints = self.hints.entry(target.to_string()).or_default();
+        match target_hints.iter_mut().find(|hint| hint.list_id == awset.id) {
+            Some(hint) => {
+                hint.awset.merge(awset);
+                hint.created_at = now_secs();
+                hint.version += 1;
+            }
+            None => target_hints.push(Hint {
+                target: target.to_string(),
+                list_id: awset.id.clone(),
+                awset: awset.clone(),
+                created_at: now_secs(),
+                version: 0,
+            }),
+        }
+        if target_hints.len() > self.config.max_hints_per_target {
+            target_hints.sort_by_key(|hint| hint.created_at);
+            let dropped = target_hints.len() - self.config.max_hints_per_target;
+            target_hints.drain(..dropped);
+            crate::warn!("Hint queue for server {} is full, dropped {} hints", target, dropped);
+        }
+        self.persist(target)
+    }
+
+    pub fn targets(&self) -> Vec<String> {
+        self.hints
+            .iter()
+            .filter(|(_, hints)| !hints.is_empty())
+            .map(|(target, _)| target.clone())
+            .collect()
+    }
+
+    pub fn pending(&self, target: &str) -> Vec<Hint> {
+        self.hints.get(target).cloned().unwrap_or_default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.hints.values().map(|hints| hints.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // drops a delivered hint unless a newer write was merged into it meanwhile
+    pub fn remove_delivered(&mut self, hint: &Hint) -> io::Result<()> {
+        if let Some(target_hints) = self.hints.get_mut(&hint.target) {
+            target_hints.retain(|h| h.list_id != hint.list_id || h.version != hint.version);
+        }
+        self.persist(&hint.target)
+    }
+
+    // removes hints older than max_age, returns how many were dropped
+    pub fn expire(&mut self) -> io::Result<usize> {
+        let oldest = now_secs().saturating_sub(self.config.max_age.as_secs());
+        let mut expired = 0;
+        let mut changed = Vec::new();
+        for (target, target_hints) in self.hints.iter_mut() {
+            let before = target_hints.len();
+            target_hints.retain(|hint| hint.created_at >= oldest);
+            if target_hints.len() != before {
+                expired += before - target_hints.len();
+                changed.push(target.clone());
+            }
+        }
+        for target in changed {
+            self.persist(&target)?;
+        }
+        Ok(expired)
+    }
+
+    // admin view: pending hints per target without the list contents
+    pub fn summary(&self) -> Value {
+        let mut root = serde_json::Map::new();
+        for (target, target_hints) in &self.hints {
+            if target_hints.is_empty() {
+                continue;
+            }
+            let entries: Vec<Value> = target_hints
+                .iter()
+                .map(|hint| json!({"list_id": hint.list_id, "created_at": hint.created_at, "version": hint.version}))
+                .collect();
+            root.insert(target.clone(), Value::Array(entries));
+        }
+        Value::Object(root)
+    }
+
+    // targets come from the network
+    fn path_for(&self, target: &str) -> io::Result<PathBuf> {
+        storage::file_in(&self.dir, target, "json")
+    }
+
+    // rewrites the target's file through a synced temporary file so a crash leaves either
+    // the old or the new queue on disk
+    fn persist(&mut self, target: &str) -> io::Result<()> {
+        let path = self.path_for(target)?;
+        let target_hints = match self.hints.get(target) {
+            Some(x) if !x.is_empty() => x,
+            _ => {
+                self.hints.remove(target);
+                return match fs::remove_file(&path) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                    _ => storage::sync_dir(&path),
+                };
+            }
+        };
+        storage::write_replacing(&path, &serde_json::to_vec(target_hints)?)
+    }
+}
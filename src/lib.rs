@@ -1 +1,15 @@
-pub mod crdt;
\ No newline at end of file
+pub mod config;
+pub mod crdt;
+pub mod failure_detector;
+pub mod hints;
+pub mod log;
+pub mod membership;
+pub mod merkle;
+pub mod oplog;
+pub mod protocol;
+pub mod quorum;
+pub mod ring;
+pub mod storage;
+pub mod store;
+pub mod transport;
+pub mod wal;
@@ -0,0 +1,157 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::ring::HashRing;
+use crate::storage;
+
+// the nodes that were added to or removed from the cluster by a membership update
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MembershipChange {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl MembershipChange {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+// what a server knows about one member. Every change to the entry bumps its version, so
+// views from different servers merge entry by entry, the higher version winning, and a
+// server that left stays in the view as `left` so an older view can't bring it back
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Member {
+    pub port: String,
+    pub version: u64,
+    #[serde(default)]
+    pub left: bool,
+}
+
+// the current view of the cluster: every server id with its port, plus the
+// hashing ring built from the ids of the servers that haven't left
+#[derive(Clone, Debug)]
+pub struct Membership {
+    members: BTreeMap<String, Member>,
+    ring: HashRing,
+}
+
+impl Membership {
+    // the view of a cluster started from a list of ports, every entry at version 0
+    pub fn new(virtual_nodes: usize, members: HashMap<String, String>) -> Self {
+        let members: BTreeMap<String, Member> = members
+            .into_iter()
+            .map(|(id, port)| (id, Member { port, version: 0, left: false }))
+            .collect();
+        let ring = HashRing::with_nodes(virtual_nodes, members.keys());
+        Self { members, ring }
+    }
+
+    pub fn ring(&self) -> &HashRing {
+        &self.ring
+    }
+
+    pub fn port(&self, server_id: &str) -> Option<&String> {
+        self.members.get(server_id).filter(|member| !member.left).map(|member| &member.port)
+    }
+
+    pub fn contains(&self, server_id: &str) -> bool {
+        self.port(server_id).is_some()
+    }
+
+    // the servers in the cluster with their ports
+    pub fn members(&self) -> HashMap<String, String> {
+        self.members
+            .iter()
+            .filter(|(_, member)| !member.left)
+            .map(|(id, member)| (id.clone(), member.port.clone()))
+            .collect()
+    }
+
+    pub fn ids(&self) -> Vec<String> {
+        self.ring.nodes()
+    }
+
+    // every entry, including the servers that left, as sent to the other servers
+    pub fn view(&self) -> HashMap<String, Member> {
+        self.members.clone().into_iter().collect()
+    }
+
+    // adds a server to the view, returns false if it was already a member on the same port
+    pub fn join(&mut self, server_id: &str, port: &str) -> bool {
+        if self.port(server_id).map(String::as_str) == Some(port) {
+            return false;
+        }
+        let version = self.members.get(server_id).map_or(0, |member| member.version + 1);
+        let member = Member { port: port.to_string(), version, left: false };
+        self.members.insert(server_id.to_string(), member);
+        self.ring.add_node(server_id);
+        true
+    }
+
+    pub fn leave(&mut self, server_id: &str) -> Option<String> {
+        let member = self.members.get_mut(server_id).filter(|member| !member.left)?;
+        member.left = true;
+        member.version += 1;
+        self.ring.remove_node(server_id);
+        Some(member.port.clone())
+    }
+
+    // merges a view received from a peer, keeping the newer version of every entry
+    pub fn apply(&mut self, view: HashMap<String, Member>) -> MembershipChange {
+        let mut change = MembershipChange::default();
+        let mut incoming: Vec<(String, Member)> = view.into_iter().collect();
+        incoming.sort_by(|a, b| a.0.cmp(&b.0));
+        for (id, member) in incoming {
+            if self.members.get(&id).is_some_and(|known| known.version >= member.version) {
+                continue;
+            }
+            let was_member = self.contains(&id);
+            if member.left {
+                self.ring.remove_node(&id);
+                if was_member {
+                    change.removed.push(id.clone());
+                }
+            } else {
+                self.ring.add_node(&id);
+                if !was_member {
+                    change.added.push(id.clone());
+                }
+            }
+            self.members.insert(id, member);
+        }
+        change
+    }
+
+    // a view saved by `save`, or None when there is none
+    pub fn load(virtual_nodes: usize, path: &Path) -> io::Result<Option<Self>> {
+        let contents = match fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let view: HashMap<String, Member> = serde_json::from_str(&contents)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?;
+        let mut membership = Self::new(virtual_nodes, HashMap::new());
+        membership.apply(view);
+        Ok(Some(membership))
+    }
+
+    // kept in the server's data directory so a restarted server knows who joined and left
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        storage::write_replacing(path, &serde_json::to_vec_pretty(&self.members)?)
+    }
+}
+
+// the nodes that become home replicas of a key after the ring changed and therefore
+// need the list handed to them
+pub fn handoff_targets(old_ring: &HashRing, new_ring: &HashRing, key: &str, n: usize) -> Vec<String> {
+    let old_replicas = old_ring.preference_list(key, n);
+    new_ring
+        .preference_list(key, n)
+        .into_iter()
+        .filter(|node| !old_replicas.contains(node))
+        .collect()
+}
@@ -0,0 +1,303 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use crate::crdt::{AWSet, Causal, CausalOrder, Crdt};
+use crate::failure_detector::FailureDetector;
+use crate::protocol::{Encoding, MerkleQuery, ProtocolError, Request, Response};
+use crate::ring::hash_key;
+use crate::transport;
+
+// a fixed-shape tree: keys are bucketed into 2^depth leaves by their hash, so two
+// servers build comparable trees without agreeing on anything but the depth
+#[derive(Clone, Debug)]
+pub struct MerkleTree {
+    depth: u32,
+    // levels[0] is the root, levels[depth] the leaves; node i has children 2i and 2i + 1
+    levels: Vec<Vec<u64>>,
+    leaves: Vec<BTreeMap<String, u64>>,
+}
+
+fn combine(left: u64, right: u64) -> u64 {
+    hash_key(&format!("{:016x}{:016x}", left, right))
+}
+
+impl MerkleTree {
+    pub fn build<I>(depth: u32, entries: I) -> Self
+    where
+        I: IntoIterator<Item = (String, u64)>,
+    {
+        let leaf_count = 1usize << depth;
+        let mut leaves: Vec<BTreeMap<String, u64>> = vec![BTreeMap::new(); leaf_count];
+        for (key, digest) in entries {
+            let bucket = Self::bucket_for(depth, &key);
+            leaves[bucket].insert(key, digest);
+        }
+
+        let leaf_hashes: Vec<u64> = leaves
+            .iter()
+            .map(|leaf| {
+                let mut contents = String::new();
+                for (key, digest) in leaf {
+                    contents.push_str(&format!("{}={:016x};", key, digest));
+                }
+                hash_key(&contents)
+            })
+            .collect();
+
+        let mut levels = vec![leaf_hashes];
+        while levels[0].len() > 1 {
+            let parents = levels[0]
+                .chunks(2)
+                .map(|pair| combine(pair[0], pair[1]))
+                .collect();
+            levels.insert(0, parents);
+        }
+        Self { depth, levels, leaves }
+    }
+
+    pub fn bucket_for(depth: u32, key: &str) -> usize {
+        (hash_key(key) % (1u64 << depth)) as usize
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    pub fn root(&self) -> u64 {
+        self.levels[0][0]
+    }
+
+    // hashes of the requested nodes on one level, 0 for indices outside the tree
+    pub fn nodes(&self, level: usize, indices: &[usize]) -> Vec<u64> {
+        let row = match self.levels.get(level) {
+            Some(x) => x,
+            None => return vec![0; indices.len()],
+        };
+        indices.iter().map(|i| row.get(*i).copied().unwrap_or(0)).collect()
+    }
+
+    pub fn leaf(&self, index: usize) -> BTreeMap<String, u64> {
+        self.leaves.get(index).cloned().unwrap_or_default()
+    }
+}
+
+// keys whose digest differs between the two leaves, or that only one side has
+pub fn diff_leaf(local: &BTreeMap<String, u64>, remote: &BTreeMap<String, u64>) -> Vec<String> {
+    let mut keys: Vec<String> = local
+        .iter()
+        .filter(|(key, digest)| remote.get(*key) != Some(digest))
+        .map(|(key, _)| key.clone())
+        .collect();
+    for key in remote.keys() {
+        if !local.contains_key(key) {
+            keys.push(key.clone());
+        }
+    }
+    keys
+}
+
+// digest of a list: its serialized form is canonical, entries and dots kept in order
+pub fn list_digest(awset: &AWSet) -> u64 {
+    hash_key(&serde_json::to_string(awset).unwrap())
+}
+
+// answers a tree query against the tree this server built for the requesting peer;
+// list queries are answered by the server from its own copies
+pub fn answer(tree: &MerkleTree, query: &MerkleQuery) -> Response {
+    match query {
+        MerkleQuery::Nodes { level, nodes } => Response::Nodes { hashes: tree.nodes(*level, nodes) },
+        MerkleQuery::Leaves { leaves } => Response::Leaves {
+            leaves: leaves.iter().map(|leaf| (*leaf, tree.leaf(*leaf))).collect(),
+        },
+        MerkleQuery::Lists { .. } => ProtocolError::BadRequest {
+            reason: "lists are not part of the tree".to_string(),
+        }
+        .into(),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AntiEntropyConfig {
+    pub interval: Duration,
+    pub depth: u32,
+    pub timeout: Duration,
+    pub encoding: Encoding,
+}
+
+impl Default for AntiEntropyConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(10),
+            depth: 8,
+            timeout: Duration::from_secs(5),
+            encoding: Encoding::default(),
+        }
+    }
+}
+
+// talks to one server on behalf of the anti-entropy round; `peer` is the other side
+// of the comparison, so the server builds its tree over the keys it shares with it
+struct Side<'a> {
+    context: &'a zmq::Context,
+    port: String,
+    peer: String,
+    timeout: Duration,
+    encoding: Encoding,
+}
+
+impl Side<'_> {
+    fn ask(&self, query: MerkleQuery) -> Response {
+        let request = Request::Merkle { peer: self.peer.clone(), query };
+        transport::call(self.context, &self.port, &request, self.encoding, self.timeout)
+    }
+
+    fn nodes(&self, level: usize, nodes: &[usize]) -> Option<Vec<u64>> {
+        match self.ask(MerkleQuery::Nodes { level, nodes: nodes.to_vec() }) {
+            Response::Nodes { hashes } => Some(hashes),
+            _ => None,
+        }
+    }
+
+    fn leaves(&self, leaves: &[usize]) -> Option<HashMap<usize, BTreeMap<String, u64>>> {
+        match self.ask(MerkleQuery::Leaves { leaves: leaves.to_vec() }) {
+            Response::Leaves { leaves } => Some(leaves.into_iter().collect()),
+            _ => None,
+        }
+    }
+
+    fn lists(&self, keys: &[String]) -> Option<HashMap<String, AWSet>> {
+        match self.ask(MerkleQuery::Lists { keys: keys.to_vec() }) {
+            Response::Lists { lists } => Some(lists.into_iter().map(|list| (list.id.clone(), list)).collect()),
+            _ => None,
+        }
+    }
+
+    fn push(&self, awset: &AWSet) -> bool {
+        let request = Request::Handoff { list: awset.clone() };
+        transport::call(self.context, &self.port, &request, self.encoding, self.timeout) == Response::Ack
+    }
+}
+
+// compares the trees of two servers top-down, only descending into differing subtrees,
+// and returns the keys whose lists differ
+fn differing_keys(local: &Side, remote: &Side, depth: u32) -> Option<Vec<String>> {
+    let mut differing: Vec<usize> = vec![0];
+    for level in 0..=depth as usize {
+        let local_hashes = local.nodes(level, &differing)?;
+        let remote_hashes = remote.nodes(level, &differing)?;
+        differing = differing
+            .iter()
+            .zip(local_hashes.iter().zip(remote_hashes.iter()))
+            .filter(|(_, (l, r))| l != r)
+            .map(|(node, _)| *node)
+            .collect();
+        if differing.is_empty() {
+            return Some(Vec::new());
+        }
+        if level < depth as usize {
+            differing = differing.iter().flat_map(|node| [2 * node, 2 * node + 1]).collect();
+        }
+    }
+    let local_leaves = local.leaves(&differing)?;
+    let remote_leaves = remote.leaves(&differing)?;
+    let mut keys = Vec::new();
+    for leaf in differing {
+        let empty = BTreeMap::new();
+        keys.extend(diff_leaf(
+            local_leaves.get(&leaf).unwrap_or(&empty),
+            remote_leaves.get(&leaf).unwrap_or(&empty),
+        ));
+    }
+    Some(keys)
+}
+
+// one anti-entropy round between this server and a peer: both sides end up with the
+// merge of their copies of every list that differed. Returns how many lists were repaired.
+pub fn synchronize(
+    context: &zmq::Context,
+    config: &AntiEntropyConfig,
+    (server_id, port): (&str, &str),
+    (peer_id, peer_port): (&str, &str),
+) -> Option<usize> {
+    let side = |port: &str, peer: &str| Side {
+        context,
+        port: port.to_string(),
+        peer: peer.to_string(),
+        timeout: config.timeout,
+        encoding: config.encoding,
+    };
+    let local = side(port, peer_id);
+    let remote = side(peer_port, server_id);
+
+    let keys = differing_keys(&local, &remote, config.depth)?;
+    if keys.is_empty() {
+        return Some(0);
+    }
+    let local_lists = local.lists(&keys)?;
+    let remote_lists = remote.lists(&keys)?;
+    let mut repaired = 0;
+    for key in keys {
+        // only the side that is behind gets the list, both when each has writes of its own
+        let (merged, to_local, to_remote) = match (local_lists.get(&key), remote_lists.get(&key)) {
+            (Some(l), Some(r)) => match l.compare(r) {
+                CausalOrder::Equal => continue,
+                CausalOrder::After => (l.clone(), false, true),
+                CausalOrder::Before => (r.clone(), true, false),
+                CausalOrder::Concurrent => {
+                    let mut merged = l.clone();
+                    merged.merge(r);
+                    (merged, true, true)
+                }
+            },
+            (Some(l), None) => (l.clone(), false, true),
+            (None, Some(r)) => (r.clone(), true, false),
+            (None, None) => continue,
+        };
+        if (!to_local || local.push(&merged)) && (!to_remote || remote.push(&merged)) {
+            repaired += 1;
+        }
+    }
+    Some(repaired)
+}
+
+// runs a round against the next alive peer every interval
+pub fn spawn_anti_entropy(
+    server_id: String,
+    port: String,
+    detector: Arc<Mutex<FailureDetector>>,
+    config: AntiEntropyConfig,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let context = zmq::Context::new();
+        let mut round: usize = 0;
+        loop {
+            thread::sleep(config.interval);
+            let peer = {
+                let detector = detector.lock().unwrap();
+                let mut alive: Vec<String> = detector
+                    .states()
+                    .into_iter()
+                    .filter(|(id, _)| detector.is_alive(id))
+                    .map(|(id, _)| id)
+                    .collect();
+                alive.sort();
+                if alive.is_empty() {
+                    continue;
+                }
+                let peer = alive[round % alive.len()].clone();
+                detector.port(&peer).map(|peer_port| (peer, peer_port))
+            };
+            round = round.wrapping_add(1);
+            let (peer_id, peer_port) = match peer {
+                Some(x) => x,
+                None => continue,
+            };
+            match synchronize(&context, &config, (&server_id, &port), (&peer_id, &peer_port)) {
+                Some(0) => {}
+                Some(repaired) => crate::info!("Anti-entropy with server {} repaired {} lists", peer_id, repaired),
+                None => crate::warn!("Anti-entropy with server {} did not complete", peer_id),
+            }
+        }
+    })
+}
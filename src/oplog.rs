@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::crdt::{AWSet, Crdt, CrdtError, Dot, Quantity, VersionVector};
+use crate::storage;
+
+// a change to a list as the user made it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Operation {
+    Add { name: String, target: u64, bought: u64 },
+    Remove { item_id: String },
+    Rename { item_id: String, name: String },
+    Update { item_id: String, target: u64, bought: u64 },
+    Increment { item_id: String, quantity: Quantity, amount: u64 },
+    Decrement { item_id: String, quantity: Quantity, amount: u64 },
+}
+
+impl Operation {
+    // runs the operation on the list as written by the replica, returns its delta and
+    // the id of the item it added
+    pub fn apply(&self, list: &mut AWSet, replica: &str) -> Result<(AWSet, Option<String>), CrdtError> {
+        let delta = match self {
+            Operation::Add { name, target, bought } => {
+                let (item_id, delta) = list.add(name, *target, *bought, replica);
+                return Ok((delta, Some(item_id)));
+            }
+            Operation::Remove { item_id } => list.remove(item_id, replica)?,
+            Operation::Rename { item_id, name } => list.rename(item_id, name, replica)?,
+            Operation::Update { item_id, target, bought } => list.update_item_amounts(item_id, *target, *bought, replica)?,
+            Operation::Increment { item_id, quantity, amount } => list.increment(item_id, *quantity, *amount, replica)?,
+            Operation::Decrement { item_id, quantity, amount } => list.decrement(item_id, *quantity, *amount, replica)?,
+        };
+        Ok((delta, None))
+    }
+}
+
+// an operation in the log of a list: the n-th one its issuer made, after the operations
+// of the other replicas it had delivered. `effect` is the delta the operation wrote at its
+// issuer, which every replica joins: running a remove again elsewhere would also drop the
+// writes that replica delivered and the issuer never saw
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Op {
+    pub list_id: String,
+    pub dot: Dot,
+    pub deps: VersionVector,
+    pub operation: Operation,
+    pub effect: AWSet,
+}
+
+// the operations of a list in the order they were delivered, a causal order: an operation
+// is only delivered after the earlier ones of its issuer and the ones it depends on, and
+// waits in `pending` until then. The list is the join of the effects in the log
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpLog {
+    pub list_id: String,
+    // operations delivered from each replica
+    delivered: VersionVector,
+    // operations delivered before the ones in `ops`, dropped when the log was compacted
+    #[serde(default)]
+    compacted: VersionVector,
+    ops: Vec<Op>,
+    pending: Vec<Op>,
+}
+
+impl OpLog {
+    pub fn new(list_id: &str) -> Self {
+        Self { list_id: list_id.to_string(), ..Self::default() }
+    }
+
+    // runs an operation of this replica on the list and logs it, returns the op to
+    // broadcast and the id of the item it added
+    pub fn issue(&mut self, list: &mut AWSet, replica: &str, operation: Operation) -> Result<(Op, Option<String>), CrdtError> {
+        let (effect, item_id) = operation.apply(list, replica)?;
+        let counter = self.delivered.get(replica) + 1;
+        let mut deps = self.delivered.clone();
+        deps.remove(replica);
+        let op = Op {
+            list_id: self.list_id.clone(),
+            dot: Dot { replica: replica.to_string(), counter },
+            deps,
+            operation,
+            effect,
+        };
+        self.delivered.set(replica, counter);
+        self.ops.push(op.clone());
+        Ok((op, item_id))
+    }
+
+    // buffers an op from another replica and delivers every op that became deliverable,
+    // joining their effects into the list. Returns how many were delivered; ops seen
+    // before are ignored
+    pub fn receive(&mut self, list: &mut AWSet, op: Op) -> usize {
+        if self.has_delivered(&op.dot) || self.pending.iter().any(|pending| pending.dot == op.dot) {
+            return 0;
+        }
+        self.pending.push(op);
+        self.deliver_pending(list)
+    }
+
+    // takes back the last op this replica issued when it couldn't be stored, so its dot
+    // is issued again instead of after a restart that lost it
+    pub fn retract(&mut self, op: &Op) {
+        if self.ops.last() == Some(op) {
+            self.ops.pop();
+            self.delivered.set(&op.dot.replica, op.dot.counter - 1);
+        }
+    }
+
+    // catches up with the whole list of a replica that delivered `delivered`: the list
+    // holds the effects of those ops, so they count as delivered here too, and the log can
+    // no longer send them. Returns how many pending ops that let through
+    pub fn fast_forward(&mut self, list: &mut AWSet, delivered: &VersionVector) -> usize {
+        self.delivered.merge(delivered);
+        self.compacted.merge(delivered);
+        self.pending.retain(|op| !delivered.contains(&op.dot));
+        self.deliver_pending(list)
+    }
+
+    // drops the oldest delivered ops, keeping the last `keep`. A replica that misses one
+    // of them catches up from the whole list
+    pub fn compact(&mut self, keep: usize) {
+        let dropped = self.ops.len().saturating_sub(keep);
+        for op in self.ops.drain(..dropped) {
+            let counter = self.compacted.get(&op.dot.replica).max(op.dot.counter);
+            self.compacted.set(&op.dot.replica, counter);
+        }
+    }
+
+    fn deliver_pending(&mut self, list: &mut AWSet) -> usize {
+        let mut delivered = 0;
+        while let Some(i) = self.pending.iter().position(|pending| self.can_deliver(pending)) {
+            let op = self.pending.remove(i);
+            list.merge(&op.effect);
+            self.delivered.set(&op.dot.replica, op.dot.counter);
+            self.ops.push(op);
+            delivered += 1;
+        }
+        delivered
+    }
+
+    pub fn delivered(&self) -> &VersionVector {
+        &self.delivered
+    }
+
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    // ops received before the ones they depend on
+    pub fn pending(&self) -> &[Op] {
+        &self.pending
+    }
+
+    // whether `since` has every op a replica that delivered `delivered` is missing
+    pub fn can_catch_up(&self, delivered: &VersionVector) -> bool {
+        self.compacted.iter().all(|(replica, counter)| delivered.get(replica) >= counter)
+    }
+
+    // the delivered ops a replica that delivered `delivered` is missing, in causal order
+    pub fn since(&self, delivered: &VersionVector) -> Vec<Op> {
+        self.ops
+            .iter()
+            .filter(|op| !delivered.contains(&op.dot))
+            .cloned()
+            .collect()
+    }
+
+    // the list as the delivered ops left it, for a log that was never compacted
+    pub fn state(&self) -> AWSet {
+        let mut list = AWSet::new();
+        list.set_id(self.list_id.clone());
+        for op in &self.ops {
+            list.merge(&op.effect);
+        }
+        list
+    }
+
+    fn has_delivered(&self, dot: &Dot) -> bool {
+        self.delivered.contains(dot)
+    }
+
+    fn can_deliver(&self, op: &Op) -> bool {
+        self.delivered.get(&op.dot.replica) + 1 == op.dot.counter
+            && op.deps.iter().all(|(replica, counter)| self.delivered.get(replica) >= counter)
+    }
+}
+
+// a line of an op log file. A file starts with the point its log was compacted to and
+// goes on with every op the log took; a list caught up from a whole list is recorded as
+// a new starting point
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum LogRecord {
+    Delivered { delivered: VersionVector },
+    Op { op: Op },
+}
+
+// how many delivered ops a log keeps once its file has grown past it
+const MAX_OPS: usize = 500;
+
+// the op logs of a server, one file per list. Ops are appended to the file and synced;
+// once it holds more than MAX_OPS lines the log is compacted and the file rewritten
+#[derive(Debug)]
+pub struct OpLogStore {
+    dir: PathBuf,
+    logs: HashMap<String, OpLog>,
+    // lines in each list's file
+    lines: HashMap<String, usize>,
+}
+
+impl OpLogStore {
+    // reads back every log; files written before logs were appended to hold the whole log
+    // as json and are rewritten
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let mut store = Self { dir, logs: HashMap::new(), lines: HashMap::new() };
+        for entry in fs::read_dir(&store.dir)? {
+            let path = entry?.path();
+            let list_id = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(x) => x.to_string(),
+                None => continue,
+            };
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("log") => {
+                    let (log, lines) = Self::read(&list_id, &path)?;
+                    store.logs.insert(list_id.clone(), log);
+                    store.lines.insert(list_id, lines);
+                }
+                Some("json") => match serde_json::from_str::<OpLog>(&fs::read_to_string(&path)?) {
+                    Ok(log) => {
+                        store.logs.insert(list_id.clone(), log);
+                        store.rewrite(&list_id)?;
+                        fs::remove_file(&path)?;
+                    }
+                    Err(e) => crate::warn!("Ignoring unreadable op log {}: {}", path.display(), e),
+                },
+                _ => {}
+            }
+        }
+        Ok(store)
+    }
+
+    // replays the file; a line cut short by a crash ends it
+    fn read(list_id: &str, path: &Path) -> io::Result<(OpLog, usize)> {
+        let mut log = OpLog::new(list_id);
+        // the list itself is in the storage, the effects only rebuild the log
+        let mut list = AWSet::new();
+        let mut lines = 0;
+        for line in fs::read_to_string(path)?.lines() {
+            match serde_json::from_str(line) {
+                Ok(LogRecord::Delivered { delivered }) => {
+                    log.fast_forward(&mut list, &delivered);
+                }
+                Ok(LogRecord::Op { op }) => {
+                    log.receive(&mut list, op);
+                }
+                Err(e) => {
+                    crate::warn!("Ignoring the rest of op log {}: {}", path.display(), e);
+                    break;
+                }
+            }
+            lines += 1;
+        }
+        Ok((log, lines))
+    }
+
+    pub fn get(&self, list_id: &str) -> Option<&OpLog> {
+        self.logs.get(list_id)
+    }
+
+    pub fn log(&mut self, list_id: &str) -> &mut OpLog {
+        self.logs.entry(list_id.to_string()).or_insert_with(|| OpLog::new(list_id))
+    }
+
+    // stores ops the list's log issued or received; it must be on disk before an issued
+    // op is sent, or its dot could be issued again after a restart
+    pub fn append_ops(&mut self, list_id: &str, ops: &[Op]) -> io::Result<()> {
+        let records: Vec<LogRecord> = ops.iter().map(|op| LogRecord::Op { op: op.clone() }).collect();
+        self.append(list_id, &records)
+    }
+
+    // stores that the list's log caught up from a whole list
+    pub fn append_delivered(&mut self, list_id: &str, delivered: &VersionVector) -> io::Result<()> {
+        self.append(list_id, &[LogRecord::Delivered { delivered: delivered.clone() }])
+    }
+
+    fn append(&mut self, list_id: &str, records: &[LogRecord]) -> io::Result<()> {
+        let path = storage::file_in(&self.dir, list_id, "log")?;
+        let lines = self.lines.get(list_id).copied().unwrap_or(0) + records.len();
+        if lines > MAX_OPS {
+            self.log(list_id).compact(MAX_OPS / 2);
+            return self.rewrite(list_id);
+        }
+        let mut contents = Vec::new();
+        for record in records {
+            serde_json::to_writer(&mut contents, record)?;
+            contents.push(b'\n');
+        }
+        let created = !path.exists();
+        let mut file = OpenOptions::new().append(true).create(true).open(&path)?;
+        file.write_all(&contents)?;
+        file.sync_data()?;
+        if created {
+            storage::sync_dir(&path)?;
+        }
+        self.lines.insert(list_id.to_string(), lines);
+        Ok(())
+    }
+
+    // writes the list's log from its compaction point
+    fn rewrite(&mut self, list_id: &str) -> io::Result<()> {
+        let path = storage::file_in(&self.dir, list_id, "log")?;
+        let log = match self.logs.get(list_id) {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let mut records = vec![LogRecord::Delivered { delivered: log.compacted.clone() }];
+        records.extend(log.ops.iter().chain(&log.pending).map(|op| LogRecord::Op { op: op.clone() }));
+        let mut contents = Vec::new();
+        for record in &records {
+            serde_json::to_writer(&mut contents, record)?;
+            contents.push(b'\n');
+        }
+        storage::write_replacing(&path, &contents)?;
+        self.lines.insert(list_id.to_string(), records.len());
+        Ok(())
+    }
+}
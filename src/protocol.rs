@@ -0,0 +1,268 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::config;
+use crate::crdt::{AWSet, CrdtError, VersionVector};
+use crate::failure_detector::Heartbeat;
+use crate::membership::Member;
+use crate::oplog::{Op, Operation};
+use crate::quorum::RequestOptions;
+
+// bumped on every incompatible change to the messages below; a server refuses
+// messages from a different version instead of guessing what they mean
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// every message on the wire is wrapped in an envelope carrying the protocol version
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    pub version: u32,
+    pub message: T,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Request {
+    // client requests, coming from the web server through the proxy
+    Read { list_id: String, options: RequestOptions },
+    Write { list: AWSet, options: RequestOptions },
+    // the delta of a single mutation; refused with MissingDeltas when the coordinator
+    // can't join it, and the client then sends the whole list with Write
+    WriteDelta { delta: AWSet, options: RequestOptions },
+    // an operation for the coordinator to run, log and broadcast to the replicas
+    Apply { list_id: String, operation: Operation, options: RequestOptions },
+    // the local copy of a list, asked by the coordinator of a read
+    ReplicaRead { list_id: String },
+    // a copy written by the coordinator of a write or by read repair, merged with the local copy
+    ReplicaWrite { list: AWSet },
+    // the delta of a write, refused with MissingDeltas by a replica that is behind
+    ReplicaDelta { delta: AWSet },
+    // logged operations of a list in causal order, refused with MissingOps by a replica
+    // that can't deliver them all yet
+    ReplicaOps { list_id: String, ops: Vec<Op> },
+    // the whole list for a replica missing ops the coordinator's log no longer has, with
+    // the ops the list holds the effects of
+    ReplicaSnapshot { list: AWSet, delivered: VersionVector },
+    // a copy to keep on behalf of a home replica that is down
+    Hint { target: String, list: AWSet },
+    // a list this server became responsible for, merged with the local copy
+    Handoff { list: AWSet },
+    Join { id: String, port: String },
+    Members { members: HashMap<String, Member> },
+    Leave,
+    Hints,
+    Metrics,
+    Gossip { digest: HashMap<String, Heartbeat> },
+    // anti-entropy, answered over the lists the server shares with `peer`
+    Merkle { peer: String, query: MerkleQuery },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MerkleQuery {
+    Nodes { level: usize, nodes: Vec<usize> },
+    Leaves { leaves: Vec<usize> },
+    Lists { keys: Vec<String> },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Response {
+    Ack,
+    // `acks` counts the replicas the list was read from
+    List { list: AWSet, acks: usize },
+    Written { acks: usize, hinted: usize },
+    // an operation was written, `item_id` is the item it added
+    Applied { item_id: Option<String>, acks: usize, hinted: usize },
+    Members { members: HashMap<String, Member> },
+    Gossip { digest: HashMap<String, Heartbeat> },
+    Nodes { hashes: Vec<u64> },
+    // (leaf index, key -> list digest)
+    Leaves { leaves: Vec<(usize, BTreeMap<String, u64>)> },
+    Lists { lists: Vec<AWSet> },
+    Hints { summary: Value },
+    // the tombstones the server dropped from its data file when it started
+    Metrics { collected: usize },
+    Error { error: ProtocolError },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProtocolError {
+    VersionMismatch { expected: u32, found: u32 },
+    BadRequest { reason: String },
+    // the server is not a replica of the list
+    NotOwner { owner: String },
+    NotFound { list_id: String },
+    // the receiver missed earlier deltas of the list and needs its full state
+    MissingDeltas { list_id: String },
+    // the receiver keeps operations it can't deliver yet, `delivered` tells which
+    // operations of every replica it has
+    MissingOps { list_id: String, delivered: VersionVector },
+    // an operation the list can't take, like a change to an item it doesn't have
+    InvalidChange { error: CrdtError },
+    QuorumFailed { needed: usize, acks: usize, hinted: usize },
+    // the server could not be reached or did not answer in time
+    Unavailable { server: String },
+    Storage { reason: String },
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::VersionMismatch { expected, found } => {
+                write!(f, "protocol version {} expected, got {}", expected, found)
+            }
+            ProtocolError::BadRequest { reason } => write!(f, "bad request: {}", reason),
+            ProtocolError::NotOwner { owner } => write!(f, "not a replica of the list, owner is server {}", owner),
+            ProtocolError::NotFound { list_id } => write!(f, "list {} not found", list_id),
+            ProtocolError::MissingDeltas { list_id } => write!(f, "missing earlier changes of list {}", list_id),
+            ProtocolError::MissingOps { list_id, .. } => write!(f, "missing earlier operations of list {}", list_id),
+            ProtocolError::InvalidChange { error } => write!(f, "invalid change: {}", error),
+            ProtocolError::QuorumFailed { needed, acks, hinted } => {
+                write!(f, "quorum of {} not reached, {} acks ({} hinted)", needed, acks, hinted)
+            }
+            ProtocolError::Unavailable { server } => write!(f, "server {} is unavailable", server),
+            ProtocolError::Storage { reason } => write!(f, "storage error: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl From<ProtocolError> for Response {
+    fn from(error: ProtocolError) -> Self {
+        Response::Error { error }
+    }
+}
+
+impl Request {
+    // the list a client request is about
+    pub fn list_id(&self) -> Option<String> {
+        match self {
+            Request::Read { list_id, .. }
+            | Request::Apply { list_id, .. }
+            | Request::ReplicaRead { list_id }
+            | Request::ReplicaOps { list_id, .. } => Some(list_id.clone()),
+            Request::Write { list, .. }
+            | Request::WriteDelta { delta: list, .. }
+            | Request::ReplicaWrite { list }
+            | Request::ReplicaSnapshot { list, .. }
+            | Request::ReplicaDelta { delta: list }
+            | Request::Hint { list, .. }
+            | Request::Handoff { list } => Some(list.id.clone()),
+            _ => None,
+        }
+    }
+}
+
+// how messages are written on the wire. MessagePack is the compact default, JSON is
+// easier to read when debugging. A server answers in the encoding of the request, so
+// every connection settles on the encoding its sender picked.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    MessagePack,
+    Json,
+}
+
+// how the web server sends changes: the delta of the list (state-based, the default), or
+// the operation, which the coordinator logs and broadcasts in causal order
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Replication {
+    #[default]
+    State,
+    Ops,
+}
+
+#[derive(Default, Deserialize)]
+struct ProtocolConfig {
+    #[serde(default)]
+    encoding: Encoding,
+    #[serde(default)]
+    replication: Replication,
+}
+
+impl ProtocolConfig {
+    fn load(path: &str) -> Result<Self, String> {
+        config::load_json_or_default(path)
+    }
+}
+
+impl Replication {
+    // {"replication": "state" | "ops"}
+    pub fn load(path: &str) -> Result<Self, String> {
+        ProtocolConfig::load(path).map(|config| config.replication)
+    }
+}
+
+impl Encoding {
+    // {"encoding": "messagepack" | "json"}
+    pub fn load(path: &str) -> Result<Self, String> {
+        ProtocolConfig::load(path).map(|config| config.encoding)
+    }
+
+    // a json envelope always starts with '{', a MessagePack one with an array marker
+    pub fn detect(message: &[u8]) -> Self {
+        match message.iter().find(|byte| !byte.is_ascii_whitespace()) {
+            Some(b'{') => Encoding::Json,
+            _ => Encoding::MessagePack,
+        }
+    }
+}
+
+fn encode<T: Serialize>(message: &T, encoding: Encoding) -> Vec<u8> {
+    let envelope = Envelope { version: PROTOCOL_VERSION, message };
+    match encoding {
+        Encoding::Json => serde_json::to_vec(&envelope).unwrap_or_default(),
+        Encoding::MessagePack => rmp_serde::to_vec(&envelope).unwrap_or_default(),
+    }
+}
+
+fn parse<T: for<'de> Deserialize<'de>>(message: &[u8], encoding: Encoding) -> Result<T, String> {
+    match encoding {
+        Encoding::Json => serde_json::from_slice(message).map_err(|e| e.to_string()),
+        Encoding::MessagePack => rmp_serde::from_slice(message).map_err(|e| e.to_string()),
+    }
+}
+
+fn decode<T: for<'de> Deserialize<'de>>(message: &[u8]) -> Result<(T, Encoding), ProtocolError> {
+    let encoding = Encoding::detect(message);
+    let envelope: Envelope<T> = match parse(message, encoding) {
+        Ok(x) => x,
+        Err(reason) => {
+            // a peer on another version may send messages we can't parse, tell it so
+            // instead of answering with a confusing parse error
+            let version: Envelope<IgnoredAny> = parse(message, encoding)
+                .map_err(|reason| ProtocolError::BadRequest { reason: format!("invalid envelope: {}", reason) })?;
+            if version.version != PROTOCOL_VERSION {
+                return Err(ProtocolError::VersionMismatch { expected: PROTOCOL_VERSION, found: version.version });
+            }
+            return Err(ProtocolError::BadRequest { reason: format!("invalid message: {}", reason) });
+        }
+    };
+    if envelope.version != PROTOCOL_VERSION {
+        return Err(ProtocolError::VersionMismatch { expected: PROTOCOL_VERSION, found: envelope.version });
+    }
+    Ok((envelope.message, encoding))
+}
+
+pub fn encode_request(request: &Request, encoding: Encoding) -> Vec<u8> {
+    encode(request, encoding)
+}
+
+// the request and the encoding it came in, which the answer should use
+pub fn decode_request(message: &[u8]) -> Result<(Request, Encoding), ProtocolError> {
+    decode(message)
+}
+
+pub fn encode_response(response: &Response, encoding: Encoding) -> Vec<u8> {
+    encode(response, encoding)
+}
+
+// an undecodable answer is reported as an error response
+pub fn decode_response(message: &[u8]) -> Response {
+    match decode(message) {
+        Ok((x, _)) => x,
+        Err(error) => Response::Error { error },
+    }
+}
@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+use crate::config;
+
+// N copies of every list; a read waits for R replicas and a write for W acknowledgements.
+// R and W count the coordinating server itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuorumConfig {
+    pub n: usize,
+    pub r: usize,
+    pub w: usize,
+}
+
+impl Default for QuorumConfig {
+    fn default() -> Self {
+        Self { n: 3, r: 2, w: 2 }
+    }
+}
+
+impl QuorumConfig {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let config: QuorumConfig = config::load_json_or_default(path)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.n == 0 {
+            return Err("n must be at least 1".to_string());
+        }
+        if self.r == 0 || self.r > self.n {
+            return Err(format!("r must be between 1 and n ({}), got {}", self.n, self.r));
+        }
+        if self.w == 0 || self.w > self.n {
+            return Err(format!("w must be between 1 and n ({}), got {}", self.n, self.w));
+        }
+        Ok(())
+    }
+
+    // the cluster settings with a client's per-request R and W applied
+    pub fn with_options(&self, options: &RequestOptions) -> Result<Self, String> {
+        let config = Self {
+            n: self.n,
+            r: options.r.unwrap_or(self.r),
+            w: options.w.unwrap_or(self.w),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestOptions {
+    pub r: Option<usize>,
+    pub w: Option<usize>,
+}
@@ -0,0 +1,116 @@
+use std::collections::BTreeSet;
+
+// FNV-1a followed by a splitmix64 finalizer. The ring position of a key has to be
+// the same on every server process, so we can't rely on std's randomly seeded hasher.
+pub fn hash_key(key: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in key.as_bytes() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+
+// virtual nodes of different servers hashed to the same position are all kept, ordered
+// by server id, so the ring doesn't depend on the order the servers were added in
+#[derive(Clone, Debug)]
+pub struct HashRing {
+    virtual_nodes: usize,
+    ring: BTreeSet<(u64, String)>, // (position on the ring, physical node id)
+    nodes: BTreeSet<String>,
+    hash: fn(&str) -> u64,
+}
+
+impl HashRing {
+    pub fn new(virtual_nodes: usize) -> Self {
+        Self::with_hash(virtual_nodes, hash_key)
+    }
+
+    // a ring placing nodes and keys with another hash function
+    pub fn with_hash(virtual_nodes: usize, hash: fn(&str) -> u64) -> Self {
+        Self {
+            virtual_nodes: virtual_nodes.max(1),
+            ring: BTreeSet::new(),
+            nodes: BTreeSet::new(),
+            hash,
+        }
+    }
+
+    pub fn with_nodes<I, S>(virtual_nodes: usize, nodes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut ring = Self::new(virtual_nodes);
+        for node in nodes {
+            ring.add_node(node.as_ref());
+        }
+        ring
+    }
+
+    // each physical node is placed on the ring once per virtual node
+    pub fn add_node(&mut self, node_id: &str) {
+        if !self.nodes.insert(node_id.to_string()) {
+            return;
+        }
+        for vnode in 0..self.virtual_nodes {
+            let position = (self.hash)(&format!("{}#{}", node_id, vnode));
+            self.ring.insert((position, node_id.to_string()));
+        }
+    }
+
+    pub fn remove_node(&mut self, node_id: &str) {
+        if !self.nodes.remove(node_id) {
+            return;
+        }
+        self.ring.retain(|(_, node)| node != node_id);
+    }
+
+    pub fn contains(&self, node_id: &str) -> bool {
+        self.nodes.contains(node_id)
+    }
+
+    pub fn nodes(&self) -> Vec<String> {
+        self.nodes.iter().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn virtual_nodes(&self) -> usize {
+        self.virtual_nodes
+    }
+
+    // the node responsible for the key (first entry of the preference list)
+    pub fn owner(&self, key: &str) -> Option<String> {
+        self.preference_list(key, 1).into_iter().next()
+    }
+
+    // walks the ring clockwise from the key's position and collects the first
+    // n distinct physical nodes, skipping further virtual nodes of the same server
+    pub fn preference_list(&self, key: &str, n: usize) -> Vec<String> {
+        let wanted = n.min(self.nodes.len());
+        let mut result: Vec<String> = Vec::with_capacity(wanted);
+        if wanted == 0 {
+            return result;
+        }
+        let position = ((self.hash)(key), String::new());
+        let clockwise = self.ring.range(position.clone()..).chain(self.ring.range(..position));
+        for (_, node) in clockwise {
+            if !result.contains(node) {
+                result.push(node.clone());
+                if result.len() == wanted {
+                    break;
+                }
+            }
+        }
+        result
+    }
+}
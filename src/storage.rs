@@ -0,0 +1,420 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::config;
+use crate::crdt::AWSet;
+use crate::wal::LoggedStorage;
+
+// where a server keeps its lists, by list id
+pub trait Storage {
+    fn get(&self, list_id: &str) -> io::Result<Option<AWSet>>;
+    fn put(&mut self, list: &AWSet) -> io::Result<()>;
+    fn delete(&mut self, list_id: &str) -> io::Result<()>;
+    // every stored list, ordered by id
+    fn scan(&self) -> io::Result<Vec<AWSet>>;
+
+    // engines that rewrite more than the list on a put write the lists at once
+    fn put_all(&mut self, lists: &[AWSet]) -> io::Result<()> {
+        lists.iter().try_for_each(|list| self.put(list))
+    }
+
+    // writes out what the engine still holds in memory
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageEngine {
+    // every list in one json file, rewritten on each write
+    #[default]
+    Json,
+    // one json file per list in a directory
+    Files,
+    // an embedded key-value store
+    Sled,
+}
+
+impl StorageEngine {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "json" => Ok(StorageEngine::Json),
+            "files" => Ok(StorageEngine::Files),
+            "sled" => Ok(StorageEngine::Sled),
+            _ => Err(format!("Unknown storage engine {}, expected json, files or sled", name)),
+        }
+    }
+
+    // where a server keeps its lists in its data directory when the configuration doesn't say
+    pub fn default_path(&self, data_dir: &Path) -> PathBuf {
+        match self {
+            StorageEngine::Json => data_dir.join("lists.json"),
+            StorageEngine::Files => data_dir.join("lists"),
+            StorageEngine::Sled => data_dir.join("sled"),
+        }
+    }
+
+    pub fn open(&self, path: impl Into<PathBuf>) -> io::Result<Box<dyn Storage>> {
+        Ok(match self {
+            StorageEngine::Json => Box::new(JsonFileStorage::open(path)?),
+            StorageEngine::Files => Box::new(FileStorage::open(path)?),
+            StorageEngine::Sled => Box::new(SledStorage::open(path)?),
+        })
+    }
+}
+
+// {"engine": "json" | "files" | "sled", "path": "...", "log": "...", "checkpoint_every": 100};
+// "{id}" in the paths is the server id
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageConfig {
+    #[serde(default)]
+    pub engine: StorageEngine,
+    #[serde(default)]
+    pub path: Option<String>,
+    // the write-ahead log in front of the engine
+    #[serde(default)]
+    pub log: Option<String>,
+    // writes logged between two snapshots
+    #[serde(default = "default_checkpoint_every")]
+    pub checkpoint_every: usize,
+}
+
+fn default_checkpoint_every() -> usize {
+    100
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self { engine: StorageEngine::default(), path: None, log: None, checkpoint_every: default_checkpoint_every() }
+    }
+}
+
+impl StorageConfig {
+    pub fn load(path: &str) -> Result<Self, String> {
+        config::load_json_or_default(path)
+    }
+
+    pub fn path_for(&self, server_id: &str, data_dir: &Path) -> PathBuf {
+        match &self.path {
+            Some(path) => PathBuf::from(path.replace("{id}", server_id)),
+            None => self.engine.default_path(data_dir),
+        }
+    }
+
+    pub fn log_path_for(&self, server_id: &str, data_dir: &Path) -> PathBuf {
+        match &self.log {
+            Some(path) => PathBuf::from(path.replace("{id}", server_id)),
+            None => data_dir.join("wal.log"),
+        }
+    }
+
+    // the engine, holding the snapshot, behind the server's write-ahead log
+    pub fn open(&self, server_id: &str, data_dir: &Path) -> io::Result<LoggedStorage> {
+        let snapshot = self.engine.open(self.path_for(server_id, data_dir))?;
+        LoggedStorage::open(snapshot, self.log_path_for(server_id, data_dir), self.checkpoint_every)
+    }
+}
+
+// copies every list of one storage into another, returns how many were copied
+pub fn copy(from: &dyn Storage, to: &mut dyn Storage) -> io::Result<usize> {
+    let lists = from.scan()?;
+    for list in &lists {
+        to.put(list)?;
+    }
+    Ok(lists.len())
+}
+
+// where an unreadable file or directory is kept: `<path>.corrupt`, or `<path>.corrupt.<n>`
+// when earlier copies are there
+pub fn corrupt_path(path: &Path) -> PathBuf {
+    let mut corrupt = path.as_os_str().to_owned();
+    corrupt.push(".corrupt");
+    let first = PathBuf::from(corrupt);
+    let mut candidate = first.clone();
+    let mut n = 1;
+    while candidate.exists() {
+        let mut numbered = first.as_os_str().to_owned();
+        numbered.push(format!(".{}", n));
+        candidate = PathBuf::from(numbered);
+        n += 1;
+    }
+    candidate
+}
+
+// moves data that can't be read aside, so the server starts without it instead of failing
+// on every restart; its replicas hold the lists it had
+pub fn quarantine(path: &Path, error: &io::Error) -> io::Result<PathBuf> {
+    let corrupt = corrupt_path(path);
+    fs::rename(path, &corrupt)?;
+    crate::error!("{} is corrupted ({}), moved it to {}", path.display(), error, corrupt.display());
+    Ok(corrupt)
+}
+
+fn invalid_data(path: &Path, reason: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), reason))
+}
+
+// the file named after an id that came from the network, which can't name a file
+// outside the directory
+pub(crate) fn file_in(dir: &Path, id: &str, extension: &str) -> io::Result<PathBuf> {
+    if id.is_empty() || id.starts_with('.') || id.contains(['/', '\\']) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid id {:?}", id)));
+    }
+    Ok(dir.join(format!("{}.{}", id, extension)))
+}
+
+// writes through a temporary file, synced before the rename, so a crash leaves either
+// the old or the new contents. The rename is only durable once the directory is synced
+pub(crate) fn write_replacing(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    sync_dir(path)
+}
+
+// syncs the directory holding the path, so the entries created, renamed or removed in it
+// survive a crash
+pub(crate) fn sync_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    // directories can't be opened as files on every platform
+    if cfg!(unix) {
+        fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+// the layout the servers have always used: an array of lists in a single file
+#[derive(Debug)]
+pub struct JsonFileStorage {
+    path: PathBuf,
+    lists: BTreeMap<String, AWSet>,
+}
+
+impl JsonFileStorage {
+    // a missing file is an empty storage, as on a server's first start, and so is a file
+    // that can't be read once it is quarantined. Files from before the list schema version
+    // are an object of version 1 lists by id, they are read as is and rewritten on the next put
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let lists = match Self::read(&path) {
+            Ok(x) => x,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                quarantine(&path, &e)?;
+                BTreeMap::new()
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(Self { path, lists })
+    }
+
+    fn read(path: &Path) -> io::Result<BTreeMap<String, AWSet>> {
+        let contents = fs::read_to_string(path)?;
+        let json: Value = serde_json::from_str(&contents).map_err(|e| invalid_data(path, e))?;
+        let stored = match json {
+            Value::Array(lists) => lists,
+            Value::Object(lists) => lists.into_iter().map(|(list_id, body)| json!({ list_id: body })).collect(),
+            _ => return Err(invalid_data(path, "expected an array of lists")),
+        };
+        let mut lists = BTreeMap::new();
+        for list in stored {
+            let awset = AWSet::migrate(list).map_err(|e| invalid_data(path, e))?;
+            lists.insert(awset.id.clone(), awset);
+        }
+        Ok(lists)
+    }
+
+    fn persist(&self) -> io::Result<()> {
+        let lists: Vec<&AWSet> = self.lists.values().collect();
+        write_replacing(&self.path, serde_json::to_string_pretty(&lists)?.as_bytes())
+    }
+}
+
+impl Storage for JsonFileStorage {
+    fn get(&self, list_id: &str) -> io::Result<Option<AWSet>> {
+        Ok(self.lists.get(list_id).cloned())
+    }
+
+    fn put(&mut self, list: &AWSet) -> io::Result<()> {
+        self.lists.insert(list.id.clone(), list.clone());
+        self.persist()
+    }
+
+    fn put_all(&mut self, lists: &[AWSet]) -> io::Result<()> {
+        for list in lists {
+            self.lists.insert(list.id.clone(), list.clone());
+        }
+        self.persist()
+    }
+
+    fn delete(&mut self, list_id: &str) -> io::Result<()> {
+        if self.lists.remove(list_id).is_some() {
+            self.persist()?;
+        }
+        Ok(())
+    }
+
+    fn scan(&self) -> io::Result<Vec<AWSet>> {
+        Ok(self.lists.values().cloned().collect())
+    }
+}
+
+// one file per list, so a write only rewrites the list it changed
+#[derive(Debug)]
+pub struct FileStorage {
+    dir: PathBuf,
+}
+
+impl FileStorage {
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    // list ids come from clients
+    fn path_for(&self, list_id: &str) -> io::Result<PathBuf> {
+        file_in(&self.dir, list_id, "json")
+    }
+
+    fn read(path: &Path) -> io::Result<AWSet> {
+        let contents = fs::read_to_string(path)?;
+        let json: Value = serde_json::from_str(&contents).map_err(|e| invalid_data(path, e))?;
+        AWSet::migrate(json).map_err(|e| invalid_data(path, e))
+    }
+}
+
+impl Storage for FileStorage {
+    // a list file that can't be read is quarantined, the list is then missing
+    fn get(&self, list_id: &str) -> io::Result<Option<AWSet>> {
+        let path = self.path_for(list_id)?;
+        match Self::read(&path) {
+            Ok(list) => Ok(Some(list)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                quarantine(&path, &e)?;
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn put(&mut self, list: &AWSet) -> io::Result<()> {
+        write_replacing(&self.path_for(&list.id)?, &serde_json::to_vec(list)?)
+    }
+
+    fn delete(&mut self, list_id: &str) -> io::Result<()> {
+        let path = self.path_for(list_id)?;
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => sync_dir(&path),
+        }
+    }
+
+    fn scan(&self) -> io::Result<Vec<AWSet>> {
+        let mut lists = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            match Self::read(&path) {
+                Ok(list) => lists.push(list),
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    quarantine(&path, &e)?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        lists.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(lists)
+    }
+}
+
+// lists in a sled database keyed by list id; sled keeps keys ordered, so a scan is by id
+pub struct SledStorage {
+    path: PathBuf,
+    db: sled::Db,
+}
+
+impl SledStorage {
+    // a database sled finds corrupted is quarantined and a new one started
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let db = match sled::open(&path) {
+            Ok(x) => x,
+            Err(e @ sled::Error::Corruption { .. }) => {
+                quarantine(&path, &io::Error::new(io::ErrorKind::InvalidData, e))?;
+                sled::open(&path)?
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, db })
+    }
+
+    fn decode(&self, value: &[u8]) -> io::Result<AWSet> {
+        let json: Value = serde_json::from_slice(value).map_err(|e| invalid_data(&self.path, e))?;
+        AWSet::migrate(json).map_err(|e| invalid_data(&self.path, e))
+    }
+
+    // a value that can't be read is moved to the database's "corrupt" tree, the list is
+    // then missing
+    fn decode_or_quarantine(&self, key: &[u8], value: &[u8]) -> io::Result<Option<AWSet>> {
+        match self.decode(value) {
+            Ok(list) => Ok(Some(list)),
+            Err(e) => {
+                self.db.open_tree("corrupt")?.insert(key, value)?;
+                self.db.remove(key)?;
+                self.db.flush()?;
+                crate::error!("list {} is corrupted ({}), moved it to the corrupt tree", String::from_utf8_lossy(key), e);
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl Storage for SledStorage {
+    fn get(&self, list_id: &str) -> io::Result<Option<AWSet>> {
+        match self.db.get(list_id)? {
+            Some(value) => self.decode_or_quarantine(list_id.as_bytes(), &value),
+            None => Ok(None),
+        }
+    }
+
+    // flushed before returning, a put is on disk once it is acknowledged
+    fn put(&mut self, list: &AWSet) -> io::Result<()> {
+        self.db.insert(list.id.as_str(), serde_json::to_vec(list)?)?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn delete(&mut self, list_id: &str) -> io::Result<()> {
+        self.db.remove(list_id)?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn scan(&self) -> io::Result<Vec<AWSet>> {
+        let mut lists = Vec::new();
+        for entry in self.db.iter() {
+            let (key, value) = entry?;
+            if let Some(list) = self.decode_or_quarantine(&key, &value)? {
+                lists.push(list);
+            }
+        }
+        Ok(lists)
+    }
+}
@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use std::io;
+use crate::crdt::AWSet;
+use crate::storage::Storage;
+
+// the lists a server holds, the one copy its read, write and repair paths share. Reads are
+// answered from memory; a change is written through to the storage before it is kept, so
+// memory never holds a write the storage lost
+pub struct ListStore {
+    lists: HashMap<String, AWSet>,
+    storage: Box<dyn Storage>,
+}
+
+impl ListStore {
+    // loads every list the storage holds
+    pub fn open(storage: Box<dyn Storage>) -> io::Result<Self> {
+        let lists = storage.scan()?.into_iter().map(|list| (list.id.clone(), list)).collect();
+        Ok(Self { lists, storage })
+    }
+
+    pub fn get(&self, list_id: &str) -> Option<&AWSet> {
+        self.lists.get(list_id)
+    }
+
+    // the stored list, or an empty one with that id
+    pub fn get_or_new(&self, list_id: &str) -> AWSet {
+        self.lists.get(list_id).cloned().unwrap_or_else(|| {
+            let mut list = AWSet::new();
+            list.set_id(list_id.to_string());
+            list
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &AWSet)> + '_ {
+        self.lists.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lists.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lists.is_empty()
+    }
+
+    pub fn put(&mut self, list: AWSet) -> io::Result<()> {
+        self.storage.put(&list)?;
+        self.lists.insert(list.id.clone(), list);
+        Ok(())
+    }
+
+    // writes every list to the storage again, e.g. after they were migrated when loaded
+    pub fn rewrite(&mut self) -> io::Result<()> {
+        let lists: Vec<AWSet> = self.lists.values().cloned().collect();
+        self.storage.put_all(&lists)?;
+        self.storage.flush()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.storage.flush()
+    }
+}
@@ -0,0 +1,22 @@
+use std::time::Duration;
+use crate::protocol::{self, Encoding, ProtocolError, Request, Response};
+
+// one request/response round trip on a fresh REQ socket; the socket is dropped on
+// timeout so an unresponsive server never blocks the caller's next request
+pub fn request(context: &zmq::Context, port: &str, message: &[u8], timeout: Duration) -> Option<Vec<u8>> {
+    let requester = context.socket(zmq::REQ).ok()?;
+    requester.set_linger(0).ok()?;
+    requester.set_rcvtimeo(timeout.as_millis() as i32).ok()?;
+    requester.set_sndtimeo(timeout.as_millis() as i32).ok()?;
+    requester.connect(&format!("tcp://localhost:{}", port)).ok()?;
+    requester.send(message, 0).ok()?;
+    requester.recv_bytes(0).ok()
+}
+
+// sends a typed request; a server that doesn't answer in time is reported as unavailable
+pub fn call(context: &zmq::Context, port: &str, message: &Request, encoding: Encoding, timeout: Duration) -> Response {
+    match request(context, port, &protocol::encode_request(message, encoding), timeout) {
+        Some(response) => protocol::decode_response(&response),
+        None => ProtocolError::Unavailable { server: format!("on port {}", port) }.into(),
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::crdt::AWSet;
+use crate::storage::{self, Storage};
+
+// a change to the stored lists, as appended to the log
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum WalRecord {
+    // the list as the write left it
+    Put { list: AWSet },
+    Delete { list_id: String },
+}
+
+// CRC-32 (IEEE), the checksum of every record
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+// an append-only file of records, each one framed as its length and checksum (u32, little
+// endian) followed by its json. A record is on disk once `append` returns
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    path: PathBuf,
+    file: File,
+    records: usize,
+}
+
+impl WriteAheadLog {
+    // opens the log and reads back its records. A record cut short or failing its checksum
+    // is normally the last one, written by a crash before it was acknowledged: the log is
+    // truncated there and the records before it are returned. The bytes dropped are kept in
+    // a `.corrupt` copy, in case it was the disk that damaged an earlier record
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<(Self, Vec<WalRecord>)> {
+        let path = path.into();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(&path)?;
+        // a log created here must still be there after a crash, with the records appended to it
+        storage::sync_dir(&path)?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while let Some((record, len)) = Self::decode(&contents[offset..]) {
+            records.push(record);
+            offset += len;
+        }
+        if offset < contents.len() {
+            let corrupt = storage::corrupt_path(&path);
+            std::fs::write(&corrupt, &contents[offset..])?;
+            crate::error!(
+                "Dropping {} bytes of a torn record at the end of {}, kept in {}",
+                contents.len() - offset,
+                path.display(),
+                corrupt.display()
+            );
+            file.set_len(offset as u64)?;
+            file.sync_all()?;
+        }
+        file.seek(SeekFrom::End(0))?;
+        let log = Self { path, file, records: records.len() };
+        Ok((log, records))
+    }
+
+    fn decode(bytes: &[u8]) -> Option<(WalRecord, usize)> {
+        let len = u32::from_le_bytes(bytes.get(0..4)?.try_into().ok()?) as usize;
+        let checksum = u32::from_le_bytes(bytes.get(4..8)?.try_into().ok()?);
+        let payload = bytes.get(8..8 + len)?;
+        if crc32(payload) != checksum {
+            return None;
+        }
+        let record = serde_json::from_slice(payload).ok()?;
+        Some((record, 8 + len))
+    }
+
+    // appends the record and syncs it to disk
+    pub fn append(&mut self, record: &WalRecord) -> io::Result<()> {
+        let payload = serde_json::to_vec(record)?;
+        let mut frame = Vec::with_capacity(payload.len() + 8);
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&crc32(&payload).to_le_bytes());
+        frame.extend_from_slice(&payload);
+        self.file.write_all(&frame)?;
+        self.file.sync_data()?;
+        self.records += 1;
+        Ok(())
+    }
+
+    // records since the log was last truncated
+    pub fn len(&self) -> usize {
+        self.records
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records == 0
+    }
+
+    // empties the log once its records are in a snapshot
+    pub fn truncate(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        self.records = 0;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+// a storage engine behind a write-ahead log. Writes go to the log, and are kept in memory
+// until a checkpoint writes them to the engine, which then holds the snapshot, and empties
+// the log. Opening it replays the log over the snapshot; a crash between a checkpoint and
+// the truncation only replays writes the snapshot already has
+pub struct LoggedStorage {
+    snapshot: Box<dyn Storage>,
+    log: WriteAheadLog,
+    // lists written since the last checkpoint, None for the deleted ones
+    changes: BTreeMap<String, Option<AWSet>>,
+    checkpoint_every: usize,
+}
+
+impl LoggedStorage {
+    pub fn open(snapshot: Box<dyn Storage>, log_path: impl Into<PathBuf>, checkpoint_every: usize) -> io::Result<Self> {
+        let (log, records) = WriteAheadLog::open(log_path)?;
+        let mut changes = BTreeMap::new();
+        for record in records {
+            match record {
+                WalRecord::Put { list } => changes.insert(list.id.clone(), Some(list)),
+                WalRecord::Delete { list_id } => changes.insert(list_id, None),
+            };
+        }
+        if !changes.is_empty() {
+            crate::info!("Recovered {} lists from {}", changes.len(), log.path().display());
+        }
+        Ok(Self { snapshot, log, changes, checkpoint_every: checkpoint_every.max(1) })
+    }
+
+    // writes the changes since the last checkpoint to the snapshot and empties the log
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        if self.changes.is_empty() && self.log.is_empty() {
+            return Ok(());
+        }
+        let mut puts = Vec::new();
+        for (list_id, change) in &self.changes {
+            match change {
+                Some(list) => puts.push(list.clone()),
+                None => self.snapshot.delete(list_id)?,
+            }
+        }
+        self.snapshot.put_all(&puts)?;
+        self.log.truncate()?;
+        self.changes.clear();
+        Ok(())
+    }
+
+    fn log(&mut self, record: WalRecord) -> io::Result<()> {
+        self.log.append(&record)?;
+        match record {
+            WalRecord::Put { list } => self.changes.insert(list.id.clone(), Some(list)),
+            WalRecord::Delete { list_id } => self.changes.insert(list_id, None),
+        };
+        if self.log.len() >= self.checkpoint_every {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+}
+
+impl Storage for LoggedStorage {
+    fn get(&self, list_id: &str) -> io::Result<Option<AWSet>> {
+        match self.changes.get(list_id) {
+            Some(change) => Ok(change.clone()),
+            None => self.snapshot.get(list_id),
+        }
+    }
+
+    fn put(&mut self, list: &AWSet) -> io::Result<()> {
+        self.log(WalRecord::Put { list: list.clone() })
+    }
+
+    fn delete(&mut self, list_id: &str) -> io::Result<()> {
+        self.log(WalRecord::Delete { list_id: list_id.to_string() })
+    }
+
+    fn scan(&self) -> io::Result<Vec<AWSet>> {
+        let mut lists: BTreeMap<String, AWSet> = self
+            .snapshot
+            .scan()?
+            .into_iter()
+            .map(|list| (list.id.clone(), list))
+            .collect();
+        for (list_id, change) in &self.changes {
+            match change {
+                Some(list) => lists.insert(list_id.clone(), list.clone()),
+                None => lists.remove(list_id),
+            };
+        }
+        Ok(lists.into_values().collect())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.checkpoint()
+    }
+}
@@ -0,0 +1,279 @@
+use proptest::prelude::*;
+use slde::crdt::{AWSet, Causal, CausalOrder, Crdt, CrdtError, Quantity, VersionVector};
+
+const REPLICAS: usize = 3;
+const NAMES: [&str; 4] = ["milk", "eggs", "bread", "apples"];
+
+#[derive(Clone, Debug)]
+enum Op {
+    Add { replica: usize, name: usize, target: u64 },
+    // `item` picks one of the items the replica has
+    Update { replica: usize, item: usize, target: u64 },
+    Rename { replica: usize, item: usize, name: usize },
+    Remove { replica: usize, item: usize },
+    // the replica `to` merges the state of `from`
+    Sync { from: usize, to: usize },
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        (0..REPLICAS, 0..NAMES.len(), 1..5u64).prop_map(|(replica, name, target)| Op::Add { replica, name, target }),
+        (0..REPLICAS, 0..8usize, 1..5u64).prop_map(|(replica, item, target)| Op::Update { replica, item, target }),
+        (0..REPLICAS, 0..8usize, 0..NAMES.len()).prop_map(|(replica, item, name)| Op::Rename { replica, item, name }),
+        (0..REPLICAS, 0..8usize).prop_map(|(replica, item)| Op::Remove { replica, item }),
+        (0..REPLICAS, 0..REPLICAS).prop_map(|(from, to)| Op::Sync { from, to }),
+    ]
+}
+
+fn new_list() -> AWSet {
+    let mut list = AWSet::new();
+    list.set_id("list".to_string());
+    list
+}
+
+fn pick(list: &AWSet, item: usize) -> Option<String> {
+    let ids: Vec<String> = list.elements().into_iter().map(|(item_id, _)| item_id.clone()).collect();
+    if ids.is_empty() {
+        None
+    } else {
+        Some(ids[item % ids.len()].clone())
+    }
+}
+
+// runs the operations on REPLICAS copies of the same list
+fn run(ops: &[Op]) -> Vec<AWSet> {
+    let mut replicas: Vec<AWSet> = (0..REPLICAS).map(|_| new_list()).collect();
+    for op in ops {
+        match op {
+            Op::Add { replica, name, target } => {
+                replicas[*replica].add(NAMES[*name], *target, 0, &format!("r{}", replica));
+            }
+            Op::Update { replica, item, target } => {
+                if let Some(item_id) = pick(&replicas[*replica], *item) {
+                    replicas[*replica].update_item_amounts(&item_id, *target, 0, &format!("r{}", replica)).unwrap();
+                }
+            }
+            Op::Rename { replica, item, name } => {
+                if let Some(item_id) = pick(&replicas[*replica], *item) {
+                    replicas[*replica].rename(&item_id, NAMES[*name], &format!("r{}", replica)).unwrap();
+                }
+            }
+            Op::Remove { replica, item } => {
+                if let Some(item_id) = pick(&replicas[*replica], *item) {
+                    replicas[*replica].remove(&item_id, &format!("r{}", replica)).unwrap();
+                }
+            }
+            Op::Sync { from, to } => {
+                let from = replicas[*from].clone();
+                replicas[*to].merge(&from);
+            }
+        }
+    }
+    replicas
+}
+
+fn merged(a: &AWSet, b: &AWSet) -> AWSet {
+    let mut result = a.clone();
+    result.merge(b);
+    result
+}
+
+// the replicas after the operations, all with an item added by r0 and seen by everyone
+fn with_shared_item(ops: &[Op], name: &str) -> (Vec<AWSet>, String) {
+    let mut replicas = run(ops);
+    let (item_id, _) = replicas[0].add(name, 1, 0, "r0");
+    let seen = replicas[0].clone();
+    for replica in replicas.iter_mut() {
+        replica.merge(&seen);
+    }
+    (replicas, item_id)
+}
+
+proptest! {
+    #[test]
+    fn merge_is_commutative(ops in prop::collection::vec(op(), 0..40)) {
+        let replicas = run(&ops);
+        prop_assert_eq!(merged(&replicas[0], &replicas[1]), merged(&replicas[1], &replicas[0]));
+    }
+
+    #[test]
+    fn merge_is_associative(ops in prop::collection::vec(op(), 0..40)) {
+        let r = run(&ops);
+        let left = merged(&merged(&r[0], &r[1]), &r[2]);
+        let right = merged(&r[0], &merged(&r[1], &r[2]));
+        prop_assert_eq!(left, right);
+    }
+
+    #[test]
+    fn merge_is_idempotent(ops in prop::collection::vec(op(), 0..40)) {
+        let replicas = run(&ops);
+        prop_assert_eq!(merged(&replicas[0], &replicas[0]), replicas[0].clone());
+        let once = merged(&replicas[0], &replicas[1]);
+        prop_assert_eq!(merged(&once, &replicas[1]), once);
+    }
+
+    // a list whose context includes the other's already has all of it
+    #[test]
+    fn dominating_list_absorbs_the_other(ops in prop::collection::vec(op(), 0..40)) {
+        let replicas = run(&ops);
+        for a in &replicas {
+            for b in &replicas {
+                let merged = merged(a, b);
+                match a.compare(b) {
+                    CausalOrder::Equal => prop_assert_eq!(a, b),
+                    CausalOrder::After => prop_assert_eq!(&merged, a),
+                    CausalOrder::Before => prop_assert_eq!(&merged, b),
+                    CausalOrder::Concurrent => {
+                        prop_assert_eq!(merged.compare(a), CausalOrder::After);
+                        prop_assert_eq!(merged.compare(b), CausalOrder::After);
+                    }
+                }
+            }
+        }
+    }
+
+    // a remove is a write too: the list it was made on is ahead of the one before it
+    #[test]
+    fn remove_moves_the_context(ops in prop::collection::vec(op(), 0..20), name in 0..NAMES.len()) {
+        let (mut replicas, item_id) = with_shared_item(&ops, NAMES[name]);
+        let before = replicas[0].clone();
+        replicas[0].remove(&item_id, "r0").unwrap();
+        prop_assert_eq!(replicas[0].compare(&before), CausalOrder::After);
+        prop_assert_eq!(before.compare(&replicas[0]), CausalOrder::Before);
+    }
+
+    // a write the remover had not seen survives the remove
+    #[test]
+    fn concurrent_update_wins(ops in prop::collection::vec(op(), 0..20), name in 0..NAMES.len(), target in 1..5u64) {
+        let (mut replicas, item_id) = with_shared_item(&ops, NAMES[name]);
+        replicas[0].update_item_amounts(&item_id, target, 0, "r0").unwrap();
+        replicas[1].remove(&item_id, "r1").unwrap();
+        let result = merged(&replicas[0], &replicas[1]);
+        prop_assert!(result.contains(&item_id));
+        prop_assert_eq!(result.get(&item_id).map(|item| item.target.value()), Some(target as i64));
+    }
+
+    // a removed item doesn't come back from a replica that had seen the removed version
+    #[test]
+    fn observed_remove_stays_removed(ops in prop::collection::vec(op(), 0..20), name in 0..NAMES.len()) {
+        let (mut replicas, item_id) = with_shared_item(&ops, NAMES[name]);
+        replicas[1].remove(&item_id, "r1").unwrap();
+        let result = merged(&replicas[0], &replicas[1]);
+        prop_assert!(!result.contains(&item_id));
+    }
+
+    // increments made at the same time on different replicas all count
+    #[test]
+    fn concurrent_increments_add_up(
+        ops in prop::collection::vec(op(), 0..20),
+        name in 0..NAMES.len(),
+        amounts in prop::collection::vec(0..5u64, REPLICAS),
+    ) {
+        let (mut replicas, item_id) = with_shared_item(&ops, NAMES[name]);
+        let before = replicas[0].get(&item_id).unwrap().bought.value();
+        for (i, amount) in amounts.iter().enumerate() {
+            replicas[i].increment(&item_id, Quantity::Bought, *amount, &format!("r{}", i)).unwrap();
+        }
+        let result = merged(&merged(&replicas[0], &replicas[1]), &replicas[2]);
+        let expected = before + amounts.iter().sum::<u64>() as i64;
+        prop_assert_eq!(result.get(&item_id).map(|item| item.bought.value()), Some(expected));
+    }
+
+    // amounts set at the same time are kept as siblings until someone picks one
+    #[test]
+    fn concurrent_amounts_are_siblings(ops in prop::collection::vec(op(), 0..20), a in 1..10u64, b in 1..10u64) {
+        let (mut replicas, item_id) = with_shared_item(&ops, "milk");
+        replicas[0].update_item_amounts(&item_id, a, 0, "r0").unwrap();
+        replicas[1].update_item_amounts(&item_id, b, 0, "r1").unwrap();
+        let mut result = merged(&replicas[0], &replicas[1]);
+        let siblings: Vec<(String, i64)> = result
+            .siblings(&item_id)
+            .iter()
+            .map(|sibling| (sibling.dot.replica.clone(), sibling.value.target.value()))
+            .collect();
+        prop_assert_eq!(siblings, vec![("r0".to_string(), a as i64), ("r1".to_string(), b as i64)]);
+
+        result.update_item_amounts(&item_id, b, 0, "r2").unwrap();
+        let result = merged(&merged(&result, &replicas[0]), &replicas[1]);
+        prop_assert_eq!(result.siblings(&item_id).len(), 1);
+        prop_assert_eq!(result.get(&item_id).map(|item| item.target.value()), Some(b as i64));
+    }
+
+    // concurrent renames keep both names until a rename that saw them both
+    #[test]
+    fn concurrent_renames_keep_both_names(ops in prop::collection::vec(op(), 0..20), a in 0..NAMES.len(), b in 0..NAMES.len()) {
+        let (mut replicas, item_id) = with_shared_item(&ops, "couve");
+        replicas[0].rename(&item_id, NAMES[a], "r0").unwrap();
+        replicas[1].rename(&item_id, NAMES[b], "r1").unwrap();
+        let mut result = merged(&replicas[0], &replicas[1]);
+        let item = result.get(&item_id).unwrap();
+        let mut names = item.name.values();
+        names.sort();
+        let mut expected = vec![NAMES[a], NAMES[b]];
+        expected.sort();
+        prop_assert_eq!(names, expected);
+
+        result.rename(&item_id, "couve flor", "r2").unwrap();
+        let result = merged(&merged(&result, &replicas[0]), &replicas[1]);
+        let item = result.get(&item_id).unwrap();
+        prop_assert_eq!(item.name.values(), vec!["couve flor"]);
+        prop_assert_eq!(result.find("couve flor"), vec![&item_id]);
+    }
+
+    // replicating the deltas of a replica's mutations in order rebuilds its state
+    #[test]
+    fn deltas_rebuild_the_state(ops in prop::collection::vec((0..NAMES.len(), 0..3usize), 0..30)) {
+        let mut source = new_list();
+        let mut copy = new_list();
+        for (name, kind) in ops {
+            let picked = pick(&source, name);
+            let delta = match (kind, picked) {
+                (1, Some(item_id)) => source.remove(&item_id, "r0").unwrap(),
+                (2, Some(item_id)) => source.rename(&item_id, NAMES[name], "r0").unwrap(),
+                _ => source.add(NAMES[name], 1, 0, "r0").1,
+            };
+            prop_assert!(copy.merge_delta(&delta));
+        }
+        prop_assert_eq!(copy, source);
+    }
+
+    // the serialized form keeps everything the merge needs, in both wire encodings
+    #[test]
+    fn serde_round_trip(ops in prop::collection::vec(op(), 0..40)) {
+        for list in run(&ops) {
+            let json: AWSet = serde_json::from_str(&serde_json::to_string(&list).unwrap()).unwrap();
+            prop_assert_eq!(&json, &list);
+            let messagepack: AWSet = rmp_serde::from_slice(&rmp_serde::to_vec(&list).unwrap()).unwrap();
+            prop_assert_eq!(&messagepack, &list);
+        }
+    }
+}
+
+#[test]
+fn changing_a_missing_item_is_not_present() {
+    let mut list = new_list();
+    let not_present = Err(CrdtError::NotPresent { item_id: "r0:1".to_string() });
+    assert_eq!(list.remove("r0:1", "r0"), not_present);
+    assert_eq!(list.rename("r0:1", "milk", "r0"), not_present);
+    assert_eq!(list.update_item_amounts("r0:1", 2, 1, "r0"), not_present);
+    assert_eq!(list.increment("r0:1", Quantity::Bought, 1, "r0"), not_present);
+    assert_eq!(list.decrement("r0:1", Quantity::Target, 1, "r0"), not_present);
+    assert_eq!(list, new_list());
+}
+
+#[test]
+fn version_vectors_compare() {
+    let vector = |counters: &[(&str, u64)]| {
+        let mut vv = VersionVector::new();
+        for (replica, counter) in counters {
+            vv.set(replica, *counter);
+        }
+        vv
+    };
+    let a = vector(&[("r0", 2), ("r1", 1)]);
+    assert_eq!(a.compare(&vector(&[("r0", 2), ("r1", 1), ("r2", 0)])), CausalOrder::Equal);
+    assert_eq!(a.compare(&vector(&[("r0", 1)])), CausalOrder::After);
+    assert_eq!(a.compare(&vector(&[("r0", 2), ("r1", 2)])), CausalOrder::Before);
+    assert_eq!(a.compare(&vector(&[("r0", 3), ("r1", 0)])), CausalOrder::Concurrent);
+    assert_eq!(a.get("r2"), 0);
+}
@@ -0,0 +1,14 @@
+use std::path::PathBuf;
+use slde::crdt::AWSet;
+
+// a path in the temp directory no other test uses
+pub fn scratch_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("slde_{}_{}", name, uuid::Uuid::new_v4()))
+}
+
+pub fn list(id: &str, item: &str) -> AWSet {
+    let mut list = AWSet::new();
+    list.set_id(id.to_string());
+    list.add(item, 1, 0, "r0");
+    list
+}
@@ -0,0 +1,57 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use slde::config::{self, ProxyConfig, ServerConfig, WebServerConfig};
+use slde::log::LogLevel;
+use slde::protocol::Encoding;
+use slde::quorum::QuorumConfig;
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn defaults_without_a_file() {
+    let (config, positional) = config::load::<ServerConfig>("server_defaults", &args(&["3"])).unwrap();
+    assert_eq!(config, ServerConfig::default());
+    assert_eq!(positional, ["3"]);
+    assert_eq!(config.data_dir_for("3"), Path::new("data/server_3"));
+    assert_eq!(config.bind_for("5573"), "tcp://*:5573");
+}
+
+// a file, then the environment, then the arguments
+#[test]
+fn overrides_apply_in_order() {
+    let path = std::env::temp_dir().join(format!("slde_config_{}.json", uuid::Uuid::new_v4()));
+    fs::write(&path, r#"{"data_dir": "/tmp/a/{id}", "proxy": "tcp://localhost:6560", "log_level": "debug"}"#).unwrap();
+    std::env::set_var("SLDE_SERVER_ORDER_PROXY", "tcp://localhost:7560");
+    std::env::set_var("SLDE_SERVER_ORDER_QUORUM__N", "5");
+    let (config, positional) = config::load::<ServerConfig>(
+        "server_order",
+        &args(&["--config", path.to_str().unwrap(), "0", "--quorum", r#"{"n": 3, "r": 1, "w": 1}"#, "--quorum.w", "3", "--peers", r#"{"0": "6570"}"#]),
+    )
+    .unwrap();
+    assert_eq!(positional, ["0"]);
+    assert_eq!(config.data_dir_for("0"), Path::new("/tmp/a/0"));
+    assert_eq!(config.proxy, "tcp://localhost:7560");
+    assert_eq!(config.log_level, LogLevel::Debug);
+    // the argument replaced the quorum set in the environment, then changed w
+    assert_eq!(config.quorum().unwrap(), QuorumConfig { n: 3, r: 1, w: 3 });
+    assert_eq!(config.peers().unwrap()["0"], "6570");
+    let detector = config.failure_detector(Encoding::Json).unwrap();
+    assert_eq!(detector.suspect_after, Duration::from_secs(3));
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn invalid_settings_are_refused() {
+    assert!(config::load::<ProxyConfig>("proxy_typo", &args(&["--frontned", "tcp://*:1"])).is_err());
+    assert!(config::load::<ProxyConfig>("proxy_missing", &args(&["--frontend"])).is_err());
+    assert!(config::load::<WebServerConfig>("web_level", &args(&["--log-level", "loud"])).is_err());
+    let (config, _) = config::load::<ServerConfig>("server_quorum", &args(&["--quorum", r#"{"n": 1, "r": 2, "w": 1}"#])).unwrap();
+    assert!(config.quorum().is_err());
+    let (config, _) = config::load::<ServerConfig>("server_ports", &args(&["--peers", r#"{"0": "port"}"#])).unwrap();
+    assert!(config.peers().is_err());
+    let (config, _) = config::load::<ServerConfig>("server_detector", &args(&["--failure-detector.dead-after-ms", "2000"])).unwrap();
+    assert!(config.failure_detector(Encoding::Json).is_err());
+}
@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+use slde::failure_detector::{FailureDetector, FailureDetectorConfig, Heartbeat, PeerState};
+
+fn detector() -> FailureDetector {
+    let config = FailureDetectorConfig {
+        suspect_after: Duration::from_millis(100),
+        dead_after: Duration::from_millis(300),
+        ..FailureDetectorConfig::default()
+    };
+    let mut detector = FailureDetector::new("0", config);
+    detector.add_peer("1", "5001");
+    detector
+}
+
+fn beat(counter: u64) -> HashMap<String, Heartbeat> {
+    HashMap::from([("1".to_string(), Heartbeat { incarnation: 1, counter })])
+}
+
+// a silent peer goes from alive to suspect to dead, and its next heartbeat brings it back
+#[test]
+fn silent_peers_become_suspect_then_dead() {
+    let mut detector = detector();
+    assert_eq!(detector.state("1"), PeerState::Alive);
+    thread::sleep(Duration::from_millis(150));
+    assert_eq!(detector.state("1"), PeerState::Suspect);
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(detector.state("1"), PeerState::Dead);
+
+    detector.merge(&beat(1));
+    assert_eq!(detector.state("1"), PeerState::Alive);
+    // a heartbeat that didn't move forward doesn't refresh the peer
+    thread::sleep(Duration::from_millis(150));
+    detector.merge(&beat(1));
+    assert_eq!(detector.state("1"), PeerState::Suspect);
+}
+
+#[test]
+fn failed_requests_make_a_peer_suspect() {
+    let mut detector = detector();
+    detector.report_failure("1");
+    assert_eq!(detector.state("1"), PeerState::Suspect);
+    detector.merge(&beat(1));
+    assert_eq!(detector.state("1"), PeerState::Alive);
+    assert_eq!(detector.state("0"), PeerState::Alive);
+
+    detector.remove_peer("1");
+    assert_eq!(detector.state("1"), PeerState::Dead);
+    assert!(detector.states().is_empty());
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use std::fs;
+use slde::hints::{Hint, HintQueue, HintQueueConfig};
+use common::{list, scratch_path};
+
+fn ids(hints: &[Hint]) -> Vec<&str> {
+    hints.iter().map(|hint| hint.list_id.as_str()).collect()
+}
+
+// past the cap the oldest hints of the target are dropped, the other targets keep theirs
+#[test]
+fn full_queues_drop_the_oldest_hints() {
+    let dir = scratch_path("hints_cap");
+    let config = HintQueueConfig { max_hints_per_target: 2, ..HintQueueConfig::default() };
+    let mut hints = HintQueue::open(&dir, config.clone()).unwrap();
+    hints.add("1", &list("a", "milk")).unwrap();
+    hints.add("1", &list("b", "eggs")).unwrap();
+    hints.add("2", &list("a", "milk")).unwrap();
+    hints.add("1", &list("c", "bread")).unwrap();
+    assert_eq!(ids(&hints.pending("1")), ["b", "c"]);
+    assert_eq!(ids(&hints.pending("2")), ["a"]);
+
+    // a write to a list with a pending hint is merged into it
+    let mut butter = list("b", "eggs");
+    butter.add("butter", 1, 0, "r1");
+    hints.add("1", &butter).unwrap();
+    let pending = hints.pending("1");
+    assert_eq!(pending[0].version, 1);
+    assert_eq!(pending[0].awset.elements().len(), 2);
+
+    let reopened = HintQueue::open(&dir, config).unwrap();
+    assert_eq!(reopened.len(), 3);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn old_hints_expire() {
+    let dir = scratch_path("hints_expiry");
+    let mut hints = HintQueue::open(&dir, HintQueueConfig::default()).unwrap();
+    hints.add("1", &list("a", "milk")).unwrap();
+    hints.add("2", &list("b", "eggs")).unwrap();
+    let mut stale = hints.pending("2");
+    stale[0].created_at -= 2 * 24 * 60 * 60;
+    fs::write(dir.join("2.json"), serde_json::to_vec(&stale).unwrap()).unwrap();
+
+    let mut hints = HintQueue::open(&dir, HintQueueConfig::default()).unwrap();
+    assert_eq!(hints.expire().unwrap(), 1);
+    assert_eq!(hints.targets(), ["1"]);
+    assert!(!dir.join("2.json").exists());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn targets_must_be_file_names() {
+    let dir = scratch_path("hints_targets");
+    let mut hints = HintQueue::open(&dir, HintQueueConfig::default()).unwrap();
+    for target in ["", "../1", ".hidden", "a/b"] {
+        assert!(hints.add(target, &list("a", "milk")).is_err(), "{:?}", target);
+    }
+    assert!(hints.is_empty());
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,71 @@
+use proptest::prelude::*;
+use slde::crdt::{wall_clock, AWSet, Crdt, Hlc, LWWRegister};
+
+const REPLICAS: [&str; 3] = ["r0", "r1", "r2"];
+
+fn hlc() -> impl Strategy<Value = Hlc> {
+    (0..1000u64, 0..3u32, 0..REPLICAS.len())
+        .prop_map(|(wall, logical, replica)| Hlc { wall, logical, replica: REPLICAS[replica].to_string() })
+}
+
+proptest! {
+    // a reading is later than the one it follows, even behind a clock that is ahead
+    #[test]
+    fn tick_moves_forward(seen in hlc(), replica in 0..REPLICAS.len(), now in 0..1000u64) {
+        let next = seen.tick(REPLICAS[replica], now);
+        prop_assert!(next > seen);
+        prop_assert_eq!(next.wall, seen.wall.max(now));
+        prop_assert_eq!(&next.replica, REPLICAS[replica]);
+    }
+
+    // readings of different replicas never tie, so every replica picks the same winner
+    #[test]
+    fn concurrent_writes_pick_the_same_winner(a in hlc(), b in hlc(), x in any::<u8>(), y in any::<u8>()) {
+        prop_assume!(a.replica != b.replica);
+        let mut left = LWWRegister::new();
+        left.set_at(x, a.clone());
+        let mut right = LWWRegister::new();
+        right.set_at(y, b.clone());
+        let mut ab = left.clone();
+        ab.merge(&right);
+        let mut ba = right.clone();
+        ba.merge(&left);
+        prop_assert_eq!(&ab, &ba);
+        prop_assert_eq!(*ab.get(), if a > b { x } else { y });
+    }
+
+    // a write that saw another wins over it, whatever the wall clocks of the writers say
+    #[test]
+    fn later_write_wins_despite_skew(first in 0..1000u64, second in 0..1000u64, x in any::<u8>(), y in any::<u8>()) {
+        let mut r0 = LWWRegister::new();
+        r0.set_at(x, Hlc::default().tick("r0", first));
+        let mut r1 = r0.clone();
+        let timestamp = r1.timestamp().tick("r1", second);
+        r1.set_at(y, timestamp);
+        r0.merge(&r1);
+        prop_assert_eq!(*r0.get(), y);
+    }
+}
+
+#[test]
+fn items_record_their_last_change() {
+    let before = wall_clock();
+    let mut list = AWSet::new();
+    let (milk, _) = list.add("milk", 2, 0, "r0");
+    let mut other = list.clone();
+    list.update_item_amounts(&milk, 3, 0, "r0").unwrap();
+    other.rename(&milk, "oat milk", "r1").unwrap();
+
+    let modified = other.get(&milk).unwrap().modified;
+    assert_eq!(modified.replica, "r1");
+    assert!(modified.wall >= before);
+
+    // concurrent writes: the item shows the latest, a write that saw both is after them
+    list.merge(&other);
+    let latest = list.siblings(&milk).iter().map(|sibling| sibling.value.modified.clone()).max().unwrap();
+    assert_eq!(list.get(&milk).unwrap().modified, latest);
+    list.increment(&milk, slde::crdt::Quantity::Bought, 1, "r2").unwrap();
+    let modified = list.get(&milk).unwrap().modified;
+    assert!(modified > latest);
+    assert_eq!(modified.replica, "r2");
+}
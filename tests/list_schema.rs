@@ -0,0 +1,144 @@
+use serde_json::json;
+use slde::crdt::{AWSet, Causal, CrdtError, Quantity, SCHEMA_VERSION};
+
+fn list() -> AWSet {
+    let mut list = AWSet::new();
+    list.set_id("list".to_string());
+    let (milk, _) = list.add("milk", 2, 0, "r0");
+    list.increment(&milk, Quantity::Bought, 1, "r1").unwrap();
+    list
+}
+
+// the layout documented in the README
+#[test]
+fn current_schema() {
+    let stored = serde_json::to_value(list()).unwrap();
+    // written at the wall time of the test
+    let modified = stored["items"]["entries"]["r0:1"][0]["value"]["modified"].clone();
+    assert_eq!(modified["replica"], "r1");
+    let expected = json!({
+        "version": SCHEMA_VERSION,
+        "id": "list",
+        "items": {
+            "entries": {
+                "r0:1": [{
+                    "dot": {"replica": "r1", "counter": 1},
+                    "value": {
+                        "name": {
+                            "values": [[{"replica": "r0", "counter": 1}, "milk"]],
+                            "context": {"vv": {"r0": 1}, "cloud": []}
+                        },
+                        "target": {"p": {"r0": 2}, "n": {}},
+                        "bought": {"p": {"r1": 1}, "n": {}},
+                        "modified": modified
+                    }
+                }]
+            },
+            "context": {"vv": {"r0": 1, "r1": 1}, "cloud": []}
+        }
+    });
+    assert_eq!(stored, expected);
+    let read: AWSet = serde_json::from_value(expected.clone()).unwrap();
+    assert_eq!(AWSet::migrate(expected).unwrap(), read);
+}
+
+#[test]
+fn other_schema_versions_are_refused() {
+    let mut json = serde_json::to_value(list()).unwrap();
+    json["version"] = json!(SCHEMA_VERSION + 1);
+    assert!(serde_json::from_value::<AWSet>(json.clone()).is_err());
+    assert!(matches!(AWSet::migrate(json), Err(CrdtError::Malformed { .. })));
+}
+
+// version 1 as first written: one entry per item, removed ones kept as tombstones
+#[test]
+fn version_1_lists_are_migrated() {
+    let stored = json!({
+        "list": {
+            "s": [
+                {"item_name": "milk", "target": 2, "bought": 1, "replica": "r0", "timestamp": 2, "deleted": false},
+                {"item_name": "eggs", "target": 6, "bought": 0, "replica": "r0", "timestamp": 3, "deleted": true}
+            ],
+            "c": [{"replica": "r0", "timestamp": 3}]
+        }
+    });
+    let list = AWSet::migrate(stored).unwrap();
+    assert_eq!(list.id, "list");
+    let milk = list.get("milk").unwrap();
+    assert_eq!((milk.target.value(), milk.bought.value()), (2, 1));
+    assert!(!list.contains("eggs"));
+    assert_eq!(list.context().version_vector().get("r0"), 3);
+}
+
+// version 1 as written once items had concurrent versions with their own counters
+#[test]
+fn version_1_lists_with_counters_are_migrated() {
+    let stored = json!({
+        "list": {
+            "s": [{
+                "item_name": "milk", "target": 3, "bought": 2, "replica": "r1", "timestamp": 1, "deleted": false,
+                "versions": [
+                    {"target": {"p": {"r0": 3}, "n": {}}, "bought": {"p": {"r0": 1}, "n": {}}, "replica": "r0", "timestamp": 2},
+                    {"target": {"p": {"r0": 3}, "n": {}}, "bought": {"p": {"r1": 1}, "n": {}}, "replica": "r1", "timestamp": 1}
+                ]
+            }],
+            "c": [{"replica": "r0", "timestamp": 2}],
+            "cloud": [{"replica": "r1", "timestamp": 1}]
+        }
+    });
+    let list = AWSet::migrate(stored).unwrap();
+    let milk = list.get("milk").unwrap();
+    assert_eq!((milk.target.value(), milk.bought.value()), (3, 2));
+    let read_back: AWSet = serde_json::from_str(&serde_json::to_string(&list).unwrap()).unwrap();
+    assert_eq!(read_back, list);
+}
+
+// version 3: items without a modification time
+#[test]
+fn version_3_lists_are_migrated() {
+    let mut stored = serde_json::to_value(list()).unwrap();
+    stored["version"] = json!(3);
+    stored["items"]["entries"]["r0:1"][0]["value"].as_object_mut().unwrap().remove("modified");
+    let list = AWSet::migrate(stored).unwrap();
+    let milk = list.get("r0:1").unwrap();
+    assert_eq!((milk.target.value(), milk.bought.value()), (2, 1));
+    assert_eq!(milk.modified.wall, 0);
+}
+
+// version 2: items keyed by their name, without a name register
+#[test]
+fn version_2_lists_are_migrated() {
+    let stored = json!({
+        "version": 2,
+        "id": "list",
+        "items": {
+            "entries": {
+                "milk": [{
+                    "dot": {"replica": "r1", "counter": 1},
+                    "value": {"target": {"p": {"r0": 2}, "n": {}}, "bought": {"p": {"r1": 1}, "n": {}}}
+                }]
+            },
+            "context": {"vv": {"r0": 1, "r1": 1}, "cloud": []}
+        }
+    });
+    let list = AWSet::migrate(stored).unwrap();
+    let milk = list.get("milk").unwrap();
+    assert_eq!(milk.name.values(), vec!["milk"]);
+    assert_eq!((milk.target.value(), milk.bought.value()), (2, 1));
+    assert_eq!(list.find("milk"), vec!["milk"]);
+    assert_eq!(list.context().version_vector().get("r1"), 1);
+}
+
+#[test]
+fn malformed_lists_are_an_error() {
+    let malformed = [
+        json!([]),
+        json!({"version": SCHEMA_VERSION, "id": "list"}),
+        json!({"list": {"s": []}}),
+        json!({"list": {"s": [{"item_name": "milk", "target": 1, "bought": 0, "timestamp": 1}], "c": []}}),
+        json!({"list": {"s": [{"item_name": "milk", "target": {"p": 1}, "bought": 0, "replica": "r0", "timestamp": 1}], "c": []}}),
+    ];
+    for json in malformed {
+        assert!(matches!(AWSet::migrate(json), Err(CrdtError::Malformed { .. })));
+    }
+}
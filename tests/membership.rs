@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+use slde::membership::Membership;
+
+fn cluster() -> Membership {
+    let ports = HashMap::from([("0".to_string(), "5000".to_string()), ("1".to_string(), "5001".to_string())]);
+    Membership::new(16, ports)
+}
+
+// two servers joining through different seeds end up in every view
+#[test]
+fn concurrent_joins_merge() {
+    let mut seed_a = cluster();
+    let mut seed_b = cluster();
+    seed_a.join("2", "5002");
+    seed_b.join("3", "5003");
+
+    let change = seed_a.apply(seed_b.view());
+    assert_eq!(change.added, ["3"]);
+    seed_b.apply(seed_a.view());
+    assert_eq!(seed_a.ids(), ["0", "1", "2", "3"]);
+    assert_eq!(seed_a.members(), seed_b.members());
+}
+
+// a view from before a server left doesn't bring it back, it can join again later
+#[test]
+fn servers_that_left_stay_gone() {
+    let mut view = cluster();
+    let stale = view.view();
+    view.leave("1");
+    assert!(view.apply(stale).is_empty());
+    assert_eq!(view.ids(), ["0"]);
+    assert!(!view.ring().contains("1"));
+
+    let mut other = cluster();
+    assert_eq!(other.apply(view.view()).removed, ["1"]);
+    view.join("1", "5001");
+    assert_eq!(other.apply(view.view()).added, ["1"]);
+}
+
+#[test]
+fn saved_views_are_loaded() {
+    let path = std::env::temp_dir().join(format!("slde_members_{}.json", uuid::Uuid::new_v4()));
+    assert!(Membership::load(16, &path).unwrap().is_none());
+    let mut view = cluster();
+    view.join("2", "5002");
+    view.leave("0");
+    view.save(&path).unwrap();
+
+    let loaded = Membership::load(16, &path).unwrap().unwrap();
+    assert_eq!(loaded.view(), view.view());
+    assert_eq!(loaded.ids(), ["1", "2"]);
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,63 @@
+use std::collections::BTreeSet;
+use slde::merkle::{diff_leaf, MerkleTree};
+
+fn entries(changed: &[usize]) -> Vec<(String, u64)> {
+    (0..200)
+        .map(|i| (format!("list-{}", i), if changed.contains(&i) { 1000 + i as u64 } else { i as u64 }))
+        .collect()
+}
+
+// walks both trees down from the root like an anti-entropy round, only into the
+// subtrees whose hashes differ, and diffs the leaves it reaches
+fn differing_keys(local: &MerkleTree, remote: &MerkleTree) -> BTreeSet<String> {
+    let mut nodes = vec![0];
+    for level in 0..=local.depth() as usize {
+        let ours = local.nodes(level, &nodes);
+        let theirs = remote.nodes(level, &nodes);
+        let differing: Vec<usize> = nodes
+            .iter()
+            .zip(ours.iter().zip(&theirs))
+            .filter(|(_, (ours, theirs))| ours != theirs)
+            .map(|(node, _)| *node)
+            .collect();
+        if level == local.depth() as usize {
+            return differing
+                .iter()
+                .flat_map(|leaf| diff_leaf(&local.leaf(*leaf), &remote.leaf(*leaf)))
+                .collect();
+        }
+        nodes = differing.iter().flat_map(|i| [2 * i, 2 * i + 1]).collect();
+    }
+    BTreeSet::new()
+}
+
+#[test]
+fn equal_trees_have_equal_roots() {
+    let local = MerkleTree::build(4, entries(&[]));
+    let remote = MerkleTree::build(4, entries(&[]).into_iter().rev());
+    assert_eq!(local.root(), remote.root());
+    assert!(differing_keys(&local, &remote).is_empty());
+}
+
+#[test]
+fn the_walk_finds_exactly_the_differing_keys() {
+    let local = MerkleTree::build(4, entries(&[3, 70, 71]));
+    let mut remote_entries = entries(&[]);
+    remote_entries.push(("only-remote".to_string(), 5));
+    remote_entries.retain(|(key, _)| key != "list-150");
+    let remote = MerkleTree::build(4, remote_entries);
+    assert_ne!(local.root(), remote.root());
+    let expected: BTreeSet<String> =
+        ["list-3", "list-70", "list-71", "list-150", "only-remote"].iter().map(|key| key.to_string()).collect();
+    assert_eq!(differing_keys(&local, &remote), expected);
+}
+
+#[test]
+fn diff_leaf_reports_both_sides() {
+    let local = MerkleTree::build(0, vec![("a".to_string(), 1), ("b".to_string(), 2)]);
+    let remote = MerkleTree::build(0, vec![("b".to_string(), 3), ("c".to_string(), 4)]);
+    let mut keys = diff_leaf(&local.leaf(0), &remote.leaf(0));
+    keys.sort();
+    assert_eq!(keys, ["a", "b", "c"]);
+    assert!(diff_leaf(&local.leaf(0), &local.leaf(0)).is_empty());
+}
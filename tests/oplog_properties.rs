@@ -0,0 +1,226 @@
+use proptest::prelude::*;
+use slde::crdt::{AWSet, Crdt, Quantity};
+use slde::oplog::{Op, OpLog, OpLogStore, Operation};
+
+const REPLICAS: [&str; 3] = ["s0", "s1", "s2"];
+const NAMES: [&str; 3] = ["milk", "eggs", "bread"];
+
+#[derive(Clone, Debug)]
+enum Step {
+    // `kind` picks the operation and `item` one of the items the replica has
+    Issue { replica: usize, kind: usize, item: usize },
+    // the replica receives one of the ops issued so far, in any order and maybe again
+    Deliver { to: usize, op: usize },
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    prop_oneof![
+        (0..REPLICAS.len(), 0..5usize, 0..8usize).prop_map(|(replica, kind, item)| Step::Issue { replica, kind, item }),
+        (0..REPLICAS.len(), 0..64usize).prop_map(|(to, op)| Step::Deliver { to, op }),
+    ]
+}
+
+struct Replica {
+    list: AWSet,
+    log: OpLog,
+}
+
+fn new_replica() -> Replica {
+    let mut list = AWSet::new();
+    list.set_id("list".to_string());
+    Replica { list, log: OpLog::new("list") }
+}
+
+fn operation(list: &AWSet, kind: usize, item: usize) -> Operation {
+    let ids: Vec<String> = list.elements().into_iter().map(|(item_id, _)| item_id.clone()).collect();
+    if ids.is_empty() || kind == 0 {
+        return Operation::Add { name: NAMES[item % NAMES.len()].to_string(), target: item as u64, bought: 0 };
+    }
+    let item_id = ids[item % ids.len()].clone();
+    match kind {
+        1 => Operation::Remove { item_id },
+        2 => Operation::Rename { item_id, name: NAMES[item % NAMES.len()].to_string() },
+        3 => Operation::Update { item_id, target: item as u64, bought: 1 },
+        _ => Operation::Increment { item_id, quantity: Quantity::Bought, amount: 1 },
+    }
+}
+
+// runs the steps, then delivers every op to every replica in the order given by `order`
+fn run(steps: &[Step], order: &[usize]) -> Vec<Replica> {
+    let mut replicas: Vec<Replica> = REPLICAS.iter().map(|_| new_replica()).collect();
+    let mut issued: Vec<Op> = Vec::new();
+    for step in steps {
+        match step {
+            Step::Issue { replica, kind, item } => {
+                let r = &mut replicas[*replica];
+                let operation = operation(&r.list, *kind, *item);
+                let (op, _) = r.log.issue(&mut r.list, REPLICAS[*replica], operation).unwrap();
+                issued.push(op);
+            }
+            Step::Deliver { to, op } => {
+                if !issued.is_empty() {
+                    let r = &mut replicas[*to];
+                    r.log.receive(&mut r.list, issued[op % issued.len()].clone());
+                }
+            }
+        }
+    }
+    for r in replicas.iter_mut() {
+        let mut remaining = issued.clone();
+        for i in order {
+            if remaining.is_empty() {
+                break;
+            }
+            let op = remaining.remove(i % remaining.len());
+            r.log.receive(&mut r.list, op);
+        }
+        for op in remaining {
+            r.log.receive(&mut r.list, op);
+        }
+    }
+    replicas
+}
+
+proptest! {
+    // every replica delivers every op, whatever order they arrive in, and ends with the same list
+    #[test]
+    fn replicas_converge(steps in prop::collection::vec(step(), 0..40), order in prop::collection::vec(any::<usize>(), 0..40)) {
+        let replicas = run(&steps, &order);
+        for r in &replicas {
+            prop_assert!(r.log.pending().is_empty());
+            prop_assert_eq!(&r.list, &replicas[0].list);
+            prop_assert_eq!(r.log.delivered(), replicas[0].log.delivered());
+        }
+    }
+
+    // the list is the join of the effects in the log
+    #[test]
+    fn state_is_derived_from_the_log(steps in prop::collection::vec(step(), 0..40), order in prop::collection::vec(any::<usize>(), 0..40)) {
+        for r in run(&steps, &order) {
+            prop_assert_eq!(r.log.state(), r.list);
+        }
+    }
+
+    // an op is only delivered after the earlier ops of its issuer and the ops it depends on
+    #[test]
+    fn delivery_is_causal(steps in prop::collection::vec(step(), 0..40), order in prop::collection::vec(any::<usize>(), 0..40)) {
+        for r in run(&steps, &order) {
+            let ops = r.log.ops();
+            for (i, op) in ops.iter().enumerate() {
+                let before = &ops[..i];
+                let seen = |replica: &str, counter: u64| {
+                    before.iter().any(|other| other.dot.replica == replica && other.dot.counter == counter)
+                };
+                for counter in 1..op.dot.counter {
+                    prop_assert!(seen(&op.dot.replica, counter));
+                }
+                for (replica, counter) in op.deps.iter() {
+                    for counter in 1..=counter {
+                        prop_assert!(seen(replica, counter));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn ops_wait_for_their_dependencies() {
+    let mut s0 = new_replica();
+    let (add, item_id) = s0.log.issue(&mut s0.list, "s0", Operation::Add { name: "milk".to_string(), target: 2, bought: 0 }).unwrap();
+    let item_id = item_id.unwrap();
+    let (increment, _) = s0
+        .log
+        .issue(&mut s0.list, "s0", Operation::Increment { item_id: item_id.clone(), quantity: Quantity::Bought, amount: 1 })
+        .unwrap();
+
+    let mut s1 = new_replica();
+    assert_eq!(s1.log.receive(&mut s1.list, increment.clone()), 0);
+    assert_eq!(s1.log.pending().len(), 1);
+    assert!(!s1.list.contains(&item_id));
+    assert_eq!(s1.log.receive(&mut s1.list, add.clone()), 2);
+    assert!(s1.log.pending().is_empty());
+    assert_eq!(s1.list, s0.list);
+    // ops seen before are ignored
+    assert_eq!(s1.log.receive(&mut s1.list, add), 0);
+    assert_eq!(s1.log.since(s1.log.delivered()), Vec::<Op>::new());
+    assert_eq!(s0.log.since(&Default::default()).len(), 2);
+}
+
+// a remove only drops the writes its issuer had seen, wherever it is delivered
+#[test]
+fn concurrent_write_survives_a_remove() {
+    let mut s0 = new_replica();
+    let (add, item_id) = s0.log.issue(&mut s0.list, "s0", Operation::Add { name: "milk".to_string(), target: 2, bought: 0 }).unwrap();
+    let item_id = item_id.unwrap();
+    let mut s1 = new_replica();
+    s1.log.receive(&mut s1.list, add.clone());
+
+    let (increment, _) = s1
+        .log
+        .issue(&mut s1.list, "s1", Operation::Increment { item_id: item_id.clone(), quantity: Quantity::Bought, amount: 1 })
+        .unwrap();
+    let (remove, _) = s0.log.issue(&mut s0.list, "s0", Operation::Remove { item_id: item_id.clone() }).unwrap();
+
+    let mut s2 = new_replica();
+    for op in [add, increment, remove] {
+        s2.log.receive(&mut s2.list, op);
+    }
+    assert_eq!(s2.list.get(&item_id).map(|item| item.bought.value()), Some(1));
+}
+
+// a replica missing ops the log dropped catches up from the whole list and then takes
+// the next ops as usual
+#[test]
+fn compacted_logs_catch_up_from_the_whole_list() {
+    let mut s0 = new_replica();
+    for name in NAMES {
+        s0.log.issue(&mut s0.list, "s0", Operation::Add { name: name.to_string(), target: 1, bought: 0 }).unwrap();
+    }
+    s0.log.compact(1);
+    assert_eq!(s0.log.ops().len(), 1);
+    assert!(!s0.log.can_catch_up(&Default::default()));
+    assert!(s0.log.can_catch_up(s0.log.delivered()));
+
+    let mut s1 = new_replica();
+    s1.list.merge(&s0.list);
+    s1.log.fast_forward(&mut s1.list, s0.log.delivered());
+    assert_eq!(s1.log.delivered(), s0.log.delivered());
+    let (next, _) = s0.log.issue(&mut s0.list, "s0", Operation::Add { name: "tea".to_string(), target: 1, bought: 0 }).unwrap();
+    assert_eq!(s1.log.receive(&mut s1.list, next), 1);
+    assert_eq!(s1.list, s0.list);
+}
+
+// the dot of an op that couldn't be stored is issued again
+#[test]
+fn retracted_ops_give_their_dot_back() {
+    let mut s0 = new_replica();
+    let mut list = s0.list.clone();
+    let (op, _) = s0.log.issue(&mut list, "s0", Operation::Add { name: "milk".to_string(), target: 1, bought: 0 }).unwrap();
+    s0.log.retract(&op);
+    assert!(s0.log.ops().is_empty());
+    let (again, _) = s0.log.issue(&mut s0.list, "s0", Operation::Add { name: "eggs".to_string(), target: 1, bought: 0 }).unwrap();
+    assert_eq!(again.dot, op.dot);
+}
+
+#[test]
+fn stored_logs_are_replayed() {
+    let dir = std::env::temp_dir().join(format!("slde_oplog_{}", uuid::Uuid::new_v4()));
+    let mut list = new_replica().list;
+    let mut ops = Vec::new();
+    {
+        let mut store = OpLogStore::open(&dir).unwrap();
+        for name in NAMES {
+            let operation = Operation::Add { name: name.to_string(), target: 1, bought: 0 };
+            let (op, _) = store.log("list").issue(&mut list, "s0", operation).unwrap();
+            store.append_ops("list", std::slice::from_ref(&op)).unwrap();
+            ops.push(op);
+        }
+        // list ids come from clients
+        assert!(store.append_ops("../list", &ops).is_err());
+    }
+    let mut store = OpLogStore::open(&dir).unwrap();
+    assert_eq!(store.log("list").ops(), ops.as_slice());
+    assert_eq!(store.log("list").state(), list);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,74 @@
+use proptest::prelude::*;
+use slde::crdt::{Crdt, Hlc, LWWRegister, MVRegister, ORMap};
+
+const REPLICAS: [&str; 3] = ["r0", "r1", "r2"];
+const KEYS: [&str; 3] = ["notes", "category", "assignee"];
+
+// an entry of a map of registers, written by one replica at a timestamp
+#[derive(Clone, Debug)]
+struct Write {
+    replica: usize,
+    key: usize,
+    value: u8,
+    timestamp: u64,
+}
+
+fn write() -> impl Strategy<Value = Write> {
+    (0..REPLICAS.len(), 0..KEYS.len(), any::<u8>(), 0..10u64)
+        .prop_map(|(replica, key, value, timestamp)| Write { replica, key, value, timestamp })
+}
+
+// every replica writes its own writes, without syncing
+fn lww_maps(writes: &[Write]) -> Vec<ORMap<String, LWWRegister<u8>>> {
+    let mut maps: Vec<ORMap<String, LWWRegister<u8>>> = REPLICAS.iter().map(|_| ORMap::new()).collect();
+    for w in writes {
+        let replica = REPLICAS[w.replica];
+        maps[w.replica].update(KEYS[w.key].to_string(), replica, |register| {
+            register.set_at(w.value, Hlc { wall: w.timestamp, logical: 0, replica: replica.to_string() })
+        });
+    }
+    maps
+}
+
+fn merged<T: Crdt>(a: &T, b: &T) -> T {
+    let mut result = a.clone();
+    result.merge(b);
+    result
+}
+
+proptest! {
+    #[test]
+    fn lww_map_merge_is_commutative_and_idempotent(writes in prop::collection::vec(write(), 0..30)) {
+        let maps = lww_maps(&writes);
+        let ab = merged(&maps[0], &maps[1]);
+        prop_assert_eq!(&ab, &merged(&maps[1], &maps[0]));
+        prop_assert_eq!(&merged(&ab, &maps[1]), &ab);
+    }
+
+    #[test]
+    fn lww_map_merge_is_associative(writes in prop::collection::vec(write(), 0..30)) {
+        let m = lww_maps(&writes);
+        prop_assert_eq!(merged(&merged(&m[0], &m[1]), &m[2]), merged(&m[0], &merged(&m[1], &m[2])));
+    }
+
+    // concurrent writes to a register keep every value, a later write that saw them replaces them
+    #[test]
+    fn mv_register_keeps_concurrent_values(a in any::<u8>(), b in any::<u8>(), c in any::<u8>()) {
+        let mut left: ORMap<String, MVRegister<u8>> = ORMap::new();
+        let mut right = left.clone();
+        left.update("notes".to_string(), "r0", |register| register.set(a, "r0"));
+        right.update("notes".to_string(), "r1", |register| register.set(b, "r1"));
+        let mut both = merged(&left, &right);
+        let register = both.get(&"notes".to_string()).unwrap();
+        let mut values = register.values();
+        values.sort();
+        let mut expected = vec![&a, &b];
+        expected.sort();
+        prop_assert_eq!(values, expected);
+
+        both.update("notes".to_string(), "r2", |register| register.set(c, "r2"));
+        let result = merged(&merged(&both, &left), &right);
+        let register = result.get(&"notes".to_string()).unwrap();
+        prop_assert_eq!(register.values(), vec![&c]);
+    }
+}
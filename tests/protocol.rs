@@ -0,0 +1,48 @@
+use serde_json::json;
+use slde::crdt::{AWSet, Quantity};
+use slde::protocol::{self, Encoding, ProtocolError, Request, Response, PROTOCOL_VERSION};
+use slde::quorum::RequestOptions;
+
+// a peer on another version is told so, whether or not we can parse its message
+#[test]
+fn other_versions_are_refused() {
+    let mismatch = ProtocolError::VersionMismatch { expected: PROTOCOL_VERSION, found: PROTOCOL_VERSION + 1 };
+    let known = json!({"version": PROTOCOL_VERSION + 1, "message": {"Read": {"list_id": "list", "options": {"r": 1, "w": null}}}});
+    assert_eq!(protocol::decode_request(known.to_string().as_bytes()), Err(mismatch.clone()));
+    let unknown = json!({"version": PROTOCOL_VERSION + 1, "message": {"Compact": {}}});
+    assert_eq!(protocol::decode_request(unknown.to_string().as_bytes()), Err(mismatch.clone()));
+    assert_eq!(protocol::decode_response(unknown.to_string().as_bytes()), Response::Error { error: mismatch });
+
+    let garbage = protocol::decode_request(b"{\"Read\": {}}");
+    assert!(matches!(garbage, Err(ProtocolError::BadRequest { .. })), "{:?}", garbage);
+}
+
+// every message reads back the same in both encodings, and the answer uses the encoding
+// the request came in
+#[test]
+fn messages_round_trip_in_both_encodings() {
+    let mut list = AWSet::new();
+    list.set_id("list".to_string());
+    let (milk, _) = list.add("milk", 2, 0, "r0");
+    list.increment(&milk, Quantity::Bought, 1, "r1").unwrap();
+    let requests = [
+        Request::Read { list_id: "list".to_string(), options: RequestOptions { r: Some(1), w: None } },
+        Request::Write { list: list.clone(), options: RequestOptions::default() },
+        Request::Join { id: "3".to_string(), port: "5003".to_string() },
+    ];
+    let responses = [
+        Response::Ack,
+        Response::List { list, acks: 2 },
+        ProtocolError::NotFound { list_id: "list".to_string() }.into(),
+    ];
+    for encoding in [Encoding::Json, Encoding::MessagePack] {
+        for request in &requests {
+            let message = protocol::encode_request(request, encoding);
+            assert_eq!(Encoding::detect(&message), encoding);
+            assert_eq!(protocol::decode_request(&message), Ok((request.clone(), encoding)));
+        }
+        for response in &responses {
+            assert_eq!(&protocol::decode_response(&protocol::encode_response(response, encoding)), response);
+        }
+    }
+}
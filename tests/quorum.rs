@@ -0,0 +1,21 @@
+use slde::quorum::{QuorumConfig, RequestOptions};
+
+#[test]
+fn quorums_larger_than_n_are_refused() {
+    assert!(QuorumConfig { n: 3, r: 2, w: 2 }.validate().is_ok());
+    assert!(QuorumConfig { n: 3, r: 3, w: 1 }.validate().is_ok());
+    assert!(QuorumConfig { n: 3, r: 4, w: 2 }.validate().is_err());
+    assert!(QuorumConfig { n: 3, r: 2, w: 4 }.validate().is_err());
+    assert!(QuorumConfig { n: 3, r: 0, w: 2 }.validate().is_err());
+    assert!(QuorumConfig { n: 0, r: 0, w: 0 }.validate().is_err());
+}
+
+// a client's R and W replace the cluster's, within the same bounds
+#[test]
+fn request_options_apply_to_the_cluster_quorum() {
+    let cluster = QuorumConfig::default();
+    assert_eq!(cluster.with_options(&RequestOptions::default()).unwrap(), cluster);
+    let options = RequestOptions { r: Some(1), w: Some(3) };
+    assert_eq!(cluster.with_options(&options).unwrap(), QuorumConfig { n: 3, r: 1, w: 3 });
+    assert!(cluster.with_options(&RequestOptions { r: None, w: Some(4) }).is_err());
+}
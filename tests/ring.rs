@@ -0,0 +1,56 @@
+use std::collections::BTreeSet;
+use slde::ring::HashRing;
+
+fn keys() -> Vec<String> {
+    (0..5000).map(|i| format!("list-{}", i)).collect()
+}
+
+#[test]
+fn preference_lists_hold_distinct_nodes() {
+    let ring = HashRing::with_nodes(64, ["0", "1", "2", "3", "4"]);
+    for key in keys() {
+        let replicas = ring.preference_list(&key, 3);
+        assert_eq!(replicas.len(), 3);
+        assert_eq!(replicas.iter().collect::<BTreeSet<_>>().len(), 3, "{}: {:?}", key, replicas);
+        assert_eq!(ring.owner(&key).as_ref(), replicas.first());
+    }
+    // never more replicas than nodes
+    assert_eq!(ring.preference_list("list-0", 9).len(), 5);
+}
+
+// only the keys the new node takes over change owner, about 1 in 5 of them
+#[test]
+fn a_join_moves_few_keys() {
+    let before = HashRing::with_nodes(64, ["0", "1", "2", "3"]);
+    let mut after = before.clone();
+    after.add_node("4");
+    let keys = keys();
+    let mut moved = 0;
+    for key in &keys {
+        let owner = after.owner(key).unwrap();
+        if before.owner(key).unwrap() != owner {
+            assert_eq!(owner, "4");
+            moved += 1;
+        }
+    }
+    assert!(moved > 0 && moved < keys.len() * 3 / 10, "{} of {} keys moved", moved, keys.len());
+}
+
+// with every virtual node on the same position none of them is lost, and the ring is
+// the same whatever order the nodes were added in
+#[test]
+fn colliding_virtual_nodes_are_kept() {
+    let mut ring = HashRing::with_hash(4, |_| 7);
+    let mut reversed = HashRing::with_hash(4, |_| 7);
+    for node in ["0", "1", "2"] {
+        ring.add_node(node);
+    }
+    for node in ["2", "1", "0"] {
+        reversed.add_node(node);
+    }
+    assert_eq!(ring.preference_list("list", 3), ["0", "1", "2"]);
+    assert_eq!(reversed.preference_list("list", 3), ["0", "1", "2"]);
+
+    ring.remove_node("0");
+    assert_eq!(ring.preference_list("list", 3), ["1", "2"]);
+}
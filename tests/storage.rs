@@ -0,0 +1,110 @@
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+use serde_json::json;
+use slde::storage::{self, StorageEngine};
+use common::{list, scratch_path};
+
+const ENGINES: [StorageEngine; 3] = [StorageEngine::Json, StorageEngine::Files, StorageEngine::Sled];
+
+#[test]
+fn lists_survive_a_reopen() {
+    for engine in ENGINES {
+        let path = scratch_path("reopen");
+        let milk = list("a", "milk");
+        {
+            let mut storage = engine.open(&path).unwrap();
+            storage.put(&list("b", "eggs")).unwrap();
+            storage.put(&milk).unwrap();
+            storage.put(&list("c", "bread")).unwrap();
+            storage.delete("c").unwrap();
+            // deleting a list that isn't stored is not an error
+            storage.delete("missing").unwrap();
+        }
+        let storage = engine.open(&path).unwrap();
+        assert_eq!(storage.get("a").unwrap(), Some(milk), "{:?}", engine);
+        assert_eq!(storage.get("c").unwrap(), None, "{:?}", engine);
+        let ids: Vec<String> = storage.scan().unwrap().into_iter().map(|list| list.id).collect();
+        assert_eq!(ids, ["a", "b"], "{:?}", engine);
+        let _ = fs::remove_dir_all(&path);
+        let _ = fs::remove_file(&path);
+    }
+}
+
+#[test]
+fn copy_moves_every_list() {
+    let from_path = scratch_path("from");
+    let to_path = scratch_path("to");
+    let mut from = StorageEngine::Json.open(&from_path).unwrap();
+    let lists = [list("a", "milk"), list("b", "eggs")];
+    for list in &lists {
+        from.put(list).unwrap();
+    }
+    let mut to = StorageEngine::Sled.open(&to_path).unwrap();
+    assert_eq!(storage::copy(from.as_ref(), to.as_mut()).unwrap(), 2);
+    assert_eq!(to.scan().unwrap(), lists);
+    let _ = fs::remove_file(&from_path);
+    let _ = fs::remove_dir_all(&to_path);
+}
+
+// data files from before the list schema version are read in place
+#[test]
+fn json_engine_reads_version_1_files() {
+    let path = scratch_path("v1");
+    let stored = json!({
+        "list": {"s": [{"item_name": "milk", "target": 2, "bought": 1, "replica": "r0", "timestamp": 2, "deleted": false}], "c": []}
+    });
+    fs::write(&path, stored.to_string()).unwrap();
+    let storage = StorageEngine::Json.open(&path).unwrap();
+    let list = storage.get("list").unwrap().unwrap();
+    let milk = list.get("milk").unwrap();
+    assert_eq!((milk.target.value(), milk.bought.value()), (2, 1));
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn file_engine_refuses_ids_outside_its_directory() {
+    let path = scratch_path("ids");
+    let storage = StorageEngine::Files.open(&path).unwrap();
+    assert!(storage.get("../escape").is_err());
+    let _ = fs::remove_dir_all(&path);
+}
+
+// a server's first start, before its data directory exists
+#[test]
+fn json_engine_starts_empty_without_a_file() {
+    let dir = scratch_path("first_boot");
+    let path = dir.join("data_0.json");
+    let mut storage = StorageEngine::Json.open(&path).unwrap();
+    assert!(storage.scan().unwrap().is_empty());
+    storage.put(&list("a", "milk")).unwrap();
+    assert!(path.exists());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn corrupted_files_are_quarantined() {
+    let path = scratch_path("corrupt");
+    fs::write(&path, "{\"list\": [1, 2").unwrap();
+    let storage = StorageEngine::Json.open(&path).unwrap();
+    assert!(storage.scan().unwrap().is_empty());
+    let corrupt = PathBuf::from(format!("{}.corrupt", path.display()));
+    assert_eq!(fs::read_to_string(&corrupt).unwrap(), "{\"list\": [1, 2");
+    assert!(!path.exists());
+    // a second corrupted file doesn't replace the first copy
+    fs::write(&path, "[").unwrap();
+    StorageEngine::Json.open(&path).unwrap();
+    assert_eq!(storage::corrupt_path(&path), PathBuf::from(format!("{}.corrupt.2", path.display())));
+    let _ = fs::remove_file(&corrupt);
+    let _ = fs::remove_file(format!("{}.corrupt.1", path.display()));
+
+    let dir = scratch_path("corrupt_files");
+    let mut files = StorageEngine::Files.open(&dir).unwrap();
+    files.put(&list("a", "milk")).unwrap();
+    fs::write(dir.join("b.json"), "not json").unwrap();
+    let ids: Vec<String> = files.scan().unwrap().into_iter().map(|list| list.id).collect();
+    assert_eq!(ids, ["a"]);
+    assert!(dir.join("b.json.corrupt").exists());
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use std::io;
+use slde::crdt::AWSet;
+use slde::storage::{Storage, StorageEngine};
+use slde::store::ListStore;
+use common::{list, scratch_path};
+
+// a storage whose disk is full
+struct Full;
+
+impl Storage for Full {
+    fn get(&self, _: &str) -> io::Result<Option<AWSet>> {
+        Ok(None)
+    }
+
+    fn put(&mut self, _: &AWSet) -> io::Result<()> {
+        Err(io::Error::other("no space left"))
+    }
+
+    fn delete(&mut self, _: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn scan(&self) -> io::Result<Vec<AWSet>> {
+        Ok(Vec::new())
+    }
+}
+
+#[test]
+fn writes_go_through_to_the_storage() {
+    let path = scratch_path("through");
+    let milk = list("a", "milk");
+    let mut store = ListStore::open(StorageEngine::Files.open(&path).unwrap()).unwrap();
+    store.put(milk.clone()).unwrap();
+    assert_eq!(store.get("a"), Some(&milk));
+
+    let reopened = ListStore::open(StorageEngine::Files.open(&path).unwrap()).unwrap();
+    assert_eq!(reopened.get("a"), Some(&milk));
+    assert_eq!(reopened.len(), 1);
+    let _ = std::fs::remove_dir_all(&path);
+}
+
+#[test]
+fn a_failed_write_is_not_kept() {
+    let mut store = ListStore::open(Box::new(Full)).unwrap();
+    assert!(store.put(list("a", "milk")).is_err());
+    assert!(store.get("a").is_none());
+    assert_eq!(store.get_or_new("a").id, "a");
+}
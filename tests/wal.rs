@@ -0,0 +1,95 @@
+mod common;
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use slde::storage::{Storage, StorageEngine};
+use slde::wal::{crc32, LoggedStorage, WalRecord, WriteAheadLog};
+use common::{list, scratch_path};
+
+#[test]
+fn crc32_matches_the_ieee_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
+
+#[test]
+fn torn_records_are_dropped() {
+    let path = scratch_path("torn");
+    let record = WalRecord::Put { list: list("a", "milk") };
+    {
+        let (mut log, records) = WriteAheadLog::open(&path).unwrap();
+        assert!(records.is_empty());
+        log.append(&record).unwrap();
+        log.append(&WalRecord::Delete { list_id: "b".to_string() }).unwrap();
+    }
+    // a crash in the middle of the next append
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&[200, 0, 0, 0, 1, 2]).unwrap();
+    drop(file);
+
+    let (log, records) = WriteAheadLog::open(&path).unwrap();
+    assert_eq!(records, [record, WalRecord::Delete { list_id: "b".to_string() }]);
+    assert_eq!(log.len(), 2);
+    let (_, records) = WriteAheadLog::open(&path).unwrap();
+    assert_eq!(records.len(), 2);
+    let corrupt = format!("{}.corrupt", path.display());
+    assert_eq!(fs::read(&corrupt).unwrap(), [200, 0, 0, 0, 1, 2]);
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(&corrupt);
+}
+
+#[test]
+fn a_corrupted_record_ends_the_log() {
+    let path = scratch_path("corrupt");
+    {
+        let (mut log, _) = WriteAheadLog::open(&path).unwrap();
+        log.append(&WalRecord::Put { list: list("a", "milk") }).unwrap();
+        log.append(&WalRecord::Put { list: list("b", "eggs") }).unwrap();
+    }
+    let mut contents = fs::read(&path).unwrap();
+    let last = contents.len() - 2;
+    contents[last] ^= 0xFF;
+    fs::write(&path, contents).unwrap();
+
+    let (_, records) = WriteAheadLog::open(&path).unwrap();
+    assert_eq!(records.len(), 1);
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(format!("{}.corrupt", path.display()));
+}
+
+// writes not yet checkpointed are recovered from the log after a crash
+#[test]
+fn the_log_is_replayed_over_the_snapshot() {
+    let snapshot_path = scratch_path("snapshot");
+    let log_path = scratch_path("log");
+    let milk = list("a", "milk");
+    let eggs = list("b", "eggs");
+    let butter = list("d", "butter");
+    {
+        let snapshot = StorageEngine::Json.open(&snapshot_path).unwrap();
+        let mut storage = LoggedStorage::open(snapshot, &log_path, 3).unwrap();
+        storage.put(&list("c", "bread")).unwrap();
+        storage.put(&milk).unwrap();
+        storage.put(&butter).unwrap();
+        // the checkpoint after the third write emptied the log
+        assert_eq!(fs::metadata(&log_path).unwrap().len(), 0);
+        storage.put(&eggs).unwrap();
+        storage.delete("c").unwrap();
+    }
+    let snapshot = StorageEngine::Json.open(&snapshot_path).unwrap();
+    let ids: Vec<String> = snapshot.scan().unwrap().into_iter().map(|list| list.id).collect();
+    assert_eq!(ids, ["a", "c", "d"]);
+
+    let snapshot = StorageEngine::Json.open(&snapshot_path).unwrap();
+    let mut storage = LoggedStorage::open(snapshot, &log_path, 3).unwrap();
+    assert_eq!(storage.scan().unwrap(), [milk, eggs.clone(), butter]);
+    assert_eq!(storage.get("b").unwrap(), Some(eggs));
+    assert_eq!(storage.get("c").unwrap(), None);
+
+    storage.checkpoint().unwrap();
+    assert_eq!(fs::metadata(&log_path).unwrap().len(), 0);
+    let snapshot = StorageEngine::Json.open(&snapshot_path).unwrap();
+    let ids: Vec<String> = snapshot.scan().unwrap().into_iter().map(|list| list.id).collect();
+    assert_eq!(ids, ["a", "b", "d"]);
+    let _ = fs::remove_file(&snapshot_path);
+    let _ = fs::remove_file(&log_path);
+}